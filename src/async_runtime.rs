@@ -0,0 +1,190 @@
+// Drives a node from tokio, for nodes embedded in async services. It serves the
+// same handlers over the same JSON lines as the blocking runtime, with timers on
+// tokio::time, and hands out a NodeHandle for the rest of the service to make
+// requests through the node and await their replies.
+//
+// The node isn't Send so `run` has to be awaited on the thread it was made on,
+// with a current_thread runtime or inside a LocalSet. The handle is Send and can
+// go anywhere.
+use std::{collections::BTreeSet, io, time::Instant};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    time,
+};
+
+use crate::{
+    kv::{KvError, KvService},
+    node::{Node, Output, RpcError, TimerId},
+    protocol::{Message, SpecificBodyFields},
+    runtime,
+};
+
+type Command = Box<dyn FnOnce(&mut Node) + Send>;
+
+pub struct AsyncRuntime {
+    node: Node,
+    handle: NodeHandle,
+    commands: mpsc::UnboundedReceiver<Command>,
+}
+
+// Makes requests through a running node
+#[derive(Clone)]
+pub struct NodeHandle {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl AsyncRuntime {
+    pub fn new(node: Node) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        Self {
+            node,
+            handle: NodeHandle { commands },
+            commands: receiver,
+        }
+    }
+
+    pub fn handle(&self) -> NodeHandle {
+        self.handle.clone()
+    }
+
+    // Serve the messages read from `input` until it runs out
+    pub async fn run<R, W>(self, input: R, mut output: W) -> io::Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let AsyncRuntime {
+            mut node,
+            mut commands,
+            // kept so that commands never runs dry
+            handle: _handle,
+        } = self;
+        let mut lines = input.lines();
+        let mut deadlines: BTreeSet<(Instant, TimerId)> = BTreeSet::new();
+        loop {
+            while let Some(&(deadline, id)) = deadlines.first()
+                && deadline <= Instant::now()
+            {
+                deadlines.pop_first();
+                let outputs = node.fire(id, Instant::now());
+                dispatch(outputs, &mut output, &mut deadlines).await?;
+            }
+
+            let next = deadlines.first().map(|&(deadline, _)| deadline);
+            let outputs = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => runtime::handle_line(&mut node, &line),
+                    None => break, // EOF
+                },
+                Some(command) = commands.recv() => node.act(Instant::now(), command),
+                _ = time::sleep_until(next.unwrap_or_else(Instant::now).into()), if next.is_some() => {
+                    continue
+                }
+            };
+            dispatch(outputs, &mut output, &mut deadlines).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn dispatch<W: AsyncWrite + Unpin>(
+    outputs: Vec<Output>,
+    output: &mut W,
+    deadlines: &mut BTreeSet<(Instant, TimerId)>,
+) -> io::Result<()> {
+    for out in outputs {
+        match out {
+            Output::Send(message) => {
+                let line = format!("{:}\n", json!(message));
+                output.write_all(line.as_bytes()).await?
+            }
+            Output::Timer { id, deadline } => {
+                deadlines.insert((deadline, id));
+            }
+        }
+    }
+    output.flush().await
+}
+
+impl NodeHandle {
+    // Run `action` on the node, and return what it sent through `reply`. A node
+    // that stopped running never answers, which is as good as a timeout.
+    async fn ask<T: Send + 'static>(
+        &self,
+        action: impl FnOnce(&mut Node, oneshot::Sender<T>) + Send + 'static,
+    ) -> Option<T> {
+        let (reply, answer) = oneshot::channel();
+        self.commands
+            .send(Box::new(move |node| action(node, reply)))
+            .ok()?;
+        answer.await.ok()
+    }
+
+    // Send `body` to `dest` and wait for the reply, or for the timeout error
+    pub async fn rpc(&self, dest: String, body: SpecificBodyFields) -> Result<Message, RpcError> {
+        self.ask(move |node, reply| {
+            node.rpc(dest, body, move |_, answer| {
+                let _ = reply.send(answer);
+            });
+        })
+        .await
+        .unwrap_or(Err(RpcError::Timeout))
+    }
+
+    pub async fn kv_read<V: DeserializeOwned + Send + 'static>(
+        &self,
+        service: KvService,
+        key: impl Serialize + Send + 'static,
+    ) -> Result<V, KvError> {
+        self.ask(move |node, reply| {
+            node.kv_read(service, key, move |_, answer| {
+                let _ = reply.send(answer);
+            });
+        })
+        .await
+        .unwrap_or(Err(KvError::Timeout))
+    }
+
+    pub async fn kv_write(
+        &self,
+        service: KvService,
+        key: impl Serialize + Send + 'static,
+        value: impl Serialize + Send + 'static,
+    ) -> Result<(), KvError> {
+        self.ask(move |node, reply| {
+            node.kv_write(service, key, value, move |_, answer| {
+                let _ = reply.send(answer);
+            });
+        })
+        .await
+        .unwrap_or(Err(KvError::Timeout))
+    }
+
+    pub async fn kv_cas(
+        &self,
+        service: KvService,
+        key: impl Serialize + Send + 'static,
+        from: impl Serialize + Send + 'static,
+        to: impl Serialize + Send + 'static,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError> {
+        self.ask(move |node, reply| {
+            node.kv_cas(
+                service,
+                key,
+                from,
+                to,
+                create_if_not_exists,
+                move |_, answer| {
+                    let _ = reply.send(answer);
+                },
+            );
+        })
+        .await
+        .unwrap_or(Err(KvError::Timeout))
+    }
+}
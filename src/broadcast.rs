@@ -0,0 +1,215 @@
+// Broadcast
+// https://fly.io/dist-sys/3a/
+//
+// Values are gossiped to the neighbours maelstrom's topology gives us, in batches
+// sent every GOSSIP_INTERVAL, and every node passes on the values it didn't know
+// about to its own neighbours.
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{
+    handler::{Handler, Unhandled},
+    node::{Node, RpcError},
+    protocol::{Body, Message, SpecificBodyFields},
+    wal::Record,
+};
+
+// How long a batch waits for its acknowledgement on the first attempt, doubled on
+// every consecutive failure up to RETRY_MAX_DELAY
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(400);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+// How often pending values are batched and sent to the neighbours
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(100);
+
+// Delivery state of the gossip to a single neighbour
+#[derive(Debug, Default)]
+struct Transmission {
+    // whether a batch is waiting for its multi_broadcast_ok
+    in_flight: bool,
+    // consecutive batches that went unacknowledged
+    failures: u32,
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+#[derive(Debug, Default)]
+pub struct Broadcast {
+    store: HashSet<usize>,
+    neighbours: Vec<String>,
+    // values each neighbour hasn't acknowledged yet
+    unacked: HashMap<String, HashSet<usize>>,
+    to_transmit: HashMap<String, Transmission>,
+}
+
+impl Broadcast {
+    pub(crate) fn restore_values(&mut self, values: Vec<usize>) {
+        for nei in &self.neighbours {
+            self.unacked
+                .entry(nei.clone())
+                .or_default()
+                .extend(values.iter().copied());
+        }
+        self.store.extend(values);
+    }
+
+    // Whatever the neighbours got before we went down, they're sent everything again
+    pub(crate) fn restore_neighbours(&mut self, neighbours: Vec<String>) {
+        self.neighbours = neighbours;
+        for nei in &self.neighbours {
+            self.unacked.insert(nei.clone(), self.store.clone());
+        }
+    }
+}
+
+pub struct BroadcastHandler;
+
+impl Handler for BroadcastHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|broadcast: &mut Broadcast, node| {
+            match body {
+                SpecificBodyFields::Broadcast { broadcast_message } => {
+                    // Only propagate values we didn't know about, otherwise the gossip never ends
+                    if broadcast.store.insert(broadcast_message) {
+                        node.persist(Record::Broadcast {
+                            values: vec![broadcast_message],
+                        });
+                        broadcast.queue_for_neighbours(&HashSet::from([broadcast_message]), &src);
+                    }
+                    node.reply(src, msg_id, SpecificBodyFields::BroadcastOk);
+                }
+                SpecificBodyFields::Read { key: None } => {
+                    let messages = Some(broadcast.store.clone());
+                    node.reply(
+                        src,
+                        msg_id,
+                        SpecificBodyFields::ReadOk {
+                            messages,
+                            value: None,
+                        },
+                    );
+                }
+                SpecificBodyFields::Topology { topology } => {
+                    if let Some(nei) = topology.get(node.id()) {
+                        broadcast.neighbours = nei.to_vec();
+                        node.persist(Record::Topology {
+                            neighbours: nei.to_vec(),
+                        });
+                    }
+                    node.reply(src, msg_id, SpecificBodyFields::TopologyOk);
+                }
+                SpecificBodyFields::MultiBroadcast { messages } => {
+                    let new: HashSet<usize> =
+                        messages.difference(&broadcast.store).copied().collect();
+                    if !new.is_empty() {
+                        node.persist(Record::Broadcast {
+                            values: new.iter().copied().collect(),
+                        });
+                    }
+                    broadcast.store.extend(new.iter().copied());
+                    broadcast.queue_for_neighbours(&new, &src);
+                    node.reply(src, msg_id, SpecificBodyFields::MultiBroadcastOk);
+                }
+                other => return Err(Unhandled::Passed(other)),
+            }
+            Ok(())
+        })
+    }
+
+    fn init(&self, node: &mut Node) {
+        node.schedule_every(GOSSIP_INTERVAL, |node| node.with_state(Broadcast::gossip));
+    }
+}
+
+impl Broadcast {
+    // Queue freshly learnt values for every neighbour except the one we got them from
+    fn queue_for_neighbours(&mut self, values: &HashSet<usize>, from: &str) {
+        for nei in &self.neighbours {
+            if nei != from {
+                self.unacked
+                    .entry(nei.clone())
+                    .or_default()
+                    .extend(values.iter().copied());
+            }
+        }
+    }
+
+    // Send every neighbour the values it hasn't acknowledged yet as a single batch.
+    // Values that show up while a batch is in flight wait for the next one, and a
+    // batch that isn't acknowledged in time is replaced on the next round by a
+    // fresh one carrying everything still pending, with a longer timeout.
+    fn gossip(&mut self, node: &mut Node) {
+        for nei in self.neighbours.clone() {
+            let pending = match self.unacked.get(&nei) {
+                Some(pending) if !pending.is_empty() => pending.clone(),
+                _ => continue,
+            };
+            let transmission = self.to_transmit.entry(nei.clone()).or_default();
+            if transmission.in_flight {
+                continue;
+            }
+            transmission.in_flight = true;
+            let timeout = retry_delay(transmission.failures + 1);
+
+            let dest = nei.clone();
+            let messages = pending.clone();
+            node.rpc_with_timeout(
+                nei,
+                SpecificBodyFields::MultiBroadcast { messages },
+                timeout,
+                move |node, reply| {
+                    node.with_state(|broadcast: &mut Broadcast, _| {
+                        broadcast.acknowledge(dest, pending, reply)
+                    })
+                },
+            );
+        }
+    }
+
+    fn acknowledge(
+        &mut self,
+        dest: String,
+        pending: HashSet<usize>,
+        reply: Result<Message, RpcError>,
+    ) {
+        let transmission = self.to_transmit.entry(dest.clone()).or_default();
+        transmission.in_flight = false;
+        match reply {
+            Ok(Message {
+                body:
+                    Body {
+                        specific_fields: SpecificBodyFields::MultiBroadcastOk,
+                        ..
+                    },
+                ..
+            }) => {
+                transmission.failures = 0;
+                if let Some(unacked) = self.unacked.get_mut(&dest) {
+                    unacked.retain(|value| !pending.contains(value));
+                }
+                eprintln!("BATCH OF {} ACKED BY {}", pending.len(), dest);
+            }
+            reply => {
+                transmission.failures += 1;
+                eprintln!(
+                    "BATCH OF {} TO {} FAILED {} TIME(S): {:?}",
+                    pending.len(),
+                    dest,
+                    transmission.failures,
+                    reply
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+// Checkers for what clients saw of a workload, the part maelstrom does with its
+// Clojure checkers. They take the history of every operation a client made, along
+// with when it was sent and when its reply came back, and tell whether what the
+// nodes answered is what the workload promises, explaining why when it isn't.
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{linearizable::KvOp, protocol::SpecificBodyFields};
+
+// What a client asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Echo(String),
+    Generate,
+    Broadcast(usize),
+    Add(usize),
+    Read,
+    // read, write or cas on a key of lin-kv
+    Kv { key: Value, op: KvOp },
+    // a read sent to every node once the workload is over and things had time to
+    // settle, which must see everything
+    FinalRead,
+}
+
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Ok(SpecificBodyFields),
+    Failed { code: u32, text: String },
+    // no reply, we can't tell whether it happened
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub op: Op,
+    pub node: String,
+    // since the start of the run
+    pub invoked: Duration,
+    // when the reply came back, if it did
+    pub completed: Option<Duration>,
+    pub outcome: Outcome,
+}
+
+impl Operation {
+    fn ok(&self) -> Option<&SpecificBodyFields> {
+        match &self.outcome {
+            Outcome::Ok(body) => Some(body),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} on {} at {:?}", self.op, self.node, self.invoked)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Verdict {
+    // what makes the history invalid
+    pub errors: Vec<String>,
+    // worth knowing but allowed by the workload, like stale reads
+    pub notes: Vec<String>,
+}
+
+impl Verdict {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn unexpected(&mut self, operation: &Operation, body: &SpecificBodyFields) {
+        self.errors.push(format!(
+            "{operation} was answered with {}",
+            body.type_name()
+        ));
+    }
+}
+
+// Failed operations are an error whatever the workload, none of them has a reason
+// to refuse anything
+fn failures(history: &[Operation], verdict: &mut Verdict) {
+    for operation in history {
+        if let Outcome::Failed { code, text } = &operation.outcome {
+            verdict
+                .errors
+                .push(format!("{operation} failed with {code}: {text}"));
+        }
+    }
+}
+
+pub fn echo(history: &[Operation]) -> Verdict {
+    let mut verdict = Verdict::default();
+    failures(history, &mut verdict);
+    for operation in history {
+        match (&operation.op, operation.ok()) {
+            (Op::Echo(sent), Some(SpecificBodyFields::EchoOk { echo })) if sent == echo => {}
+            (_, Some(body)) => verdict.unexpected(operation, body),
+            (_, None) => {}
+        }
+    }
+    verdict
+}
+
+// Every generated id must be globally unique
+pub fn unique_ids(history: &[Operation]) -> Verdict {
+    let mut verdict = Verdict::default();
+    failures(history, &mut verdict);
+    let mut generated: HashMap<&String, &Operation> = HashMap::new();
+    for operation in history {
+        match operation.ok() {
+            Some(SpecificBodyFields::GenerateOk { id }) => {
+                if let Some(first) = generated.insert(id, operation) {
+                    verdict.errors.push(format!(
+                        "{id} was generated twice, by {first} and by {operation}"
+                    ));
+                }
+            }
+            Some(body) => verdict.unexpected(operation, body),
+            None => {}
+        }
+    }
+    verdict
+        .notes
+        .push(format!("{} unique ids generated", generated.len()));
+    verdict
+}
+
+// Every acknowledged value must end up in the final read of every node, and no
+// node may ever read a value nobody broadcast. Reads that miss values that were
+// acknowledged before they were sent are allowed, the workload is only eventually
+// consistent, but reported as stale.
+pub fn broadcast(history: &[Operation], node_ids: &[String]) -> Verdict {
+    let mut verdict = Verdict::default();
+    failures(history, &mut verdict);
+
+    // value -> when its broadcast was acknowledged
+    let mut acknowledged: HashMap<usize, Duration> = HashMap::new();
+    let mut attempted: BTreeSet<usize> = BTreeSet::new();
+    for operation in history {
+        if let Op::Broadcast(value) = operation.op {
+            attempted.insert(value);
+            if let (Some(SpecificBodyFields::BroadcastOk), Some(completed)) =
+                (operation.ok(), operation.completed)
+            {
+                acknowledged.insert(value, completed);
+            }
+        }
+    }
+
+    let mut stale = Vec::new();
+    let mut final_reads: HashMap<&str, &Operation> = HashMap::new();
+    for operation in history {
+        let messages = match (&operation.op, operation.ok()) {
+            (_, None) | (Op::Broadcast(_), Some(SpecificBodyFields::BroadcastOk)) => continue,
+            (
+                Op::Read | Op::FinalRead,
+                Some(SpecificBodyFields::ReadOk {
+                    messages: Some(messages),
+                    ..
+                }),
+            ) => messages,
+            (_, Some(body)) => {
+                verdict.unexpected(operation, body);
+                continue;
+            }
+        };
+
+        let unknown: BTreeSet<_> = messages
+            .iter()
+            .filter(|value| !attempted.contains(value))
+            .collect();
+        if !unknown.is_empty() {
+            verdict.errors.push(format!(
+                "{operation} read values never broadcast: {unknown:?}"
+            ));
+        }
+
+        if operation.op == Op::FinalRead {
+            final_reads.insert(&operation.node, operation);
+            let lost: BTreeSet<_> = acknowledged
+                .keys()
+                .filter(|value| !messages.contains(value))
+                .collect();
+            if !lost.is_empty() {
+                verdict.errors.push(format!(
+                    "{} lost {} acknowledged value(s): {lost:?}",
+                    operation.node,
+                    lost.len()
+                ));
+            }
+        } else {
+            let missing: BTreeSet<_> = acknowledged
+                .iter()
+                .filter(|(value, at)| **at < operation.invoked && !messages.contains(value))
+                .map(|(value, _)| value)
+                .collect();
+            if !missing.is_empty() {
+                stale.push((operation, missing));
+            }
+        }
+    }
+
+    for node in node_ids {
+        if !final_reads.contains_key(node.as_str()) {
+            verdict
+                .errors
+                .push(format!("{node} never answered its final read"));
+        }
+    }
+    if !stale.is_empty() {
+        verdict.notes.push(format!(
+            "{} stale read(s) out of {} reads",
+            stale.len(),
+            history.iter().filter(|o| o.op == Op::Read).count()
+        ));
+        // the read that missed a value acknowledged the longest before it was sent
+        let staleness = |(operation, missing): &(&Operation, BTreeSet<&usize>)| {
+            missing
+                .iter()
+                .map(|value| operation.invoked - acknowledged[value])
+                .max()
+                .unwrap_or_default()
+        };
+        if let Some(worst) = stale.iter().max_by_key(|read| staleness(read)) {
+            verdict.notes.push(format!(
+                "stalest: {} missed {:?}, acknowledged up to {:?} before",
+                worst.0,
+                worst.1,
+                staleness(worst)
+            ));
+        }
+    }
+    verdict.notes.push(format!(
+        "{} of {} broadcast values acknowledged",
+        acknowledged.len(),
+        attempted.len()
+    ));
+    verdict
+}
+
+// Every final read must be at least the sum of the acknowledged adds and at most
+// the sum of every add that may have happened
+pub fn g_counter(history: &[Operation], node_ids: &[String]) -> Verdict {
+    let mut verdict = Verdict::default();
+    failures(history, &mut verdict);
+
+    let (mut lower, mut upper) = (0, 0);
+    for operation in history {
+        if let Op::Add(delta) = operation.op {
+            match operation.outcome {
+                Outcome::Ok(_) => {
+                    lower += delta;
+                    upper += delta;
+                }
+                Outcome::TimedOut => upper += delta,
+                Outcome::Failed { .. } => {}
+            }
+        }
+    }
+
+    let mut final_reads = BTreeSet::new();
+    for operation in history {
+        match (&operation.op, operation.ok()) {
+            (_, None) | (Op::Add(_), Some(SpecificBodyFields::AddOk)) => {}
+            (
+                Op::Read | Op::FinalRead,
+                Some(SpecificBodyFields::ReadOk {
+                    value: Some(value), ..
+                }),
+            ) => {
+                if operation.op == Op::FinalRead {
+                    final_reads.insert(&operation.node);
+                    match value.as_u64().map(|v| v as usize) {
+                        Some(value) if (lower..=upper).contains(&value) => {}
+                        _ => verdict.errors.push(format!(
+                            "{operation} read {value}, expected between {lower} and {upper}"
+                        )),
+                    }
+                }
+            }
+            (_, Some(body)) => verdict.unexpected(operation, body),
+        }
+    }
+
+    for node in node_ids {
+        if !final_reads.contains(node) {
+            verdict
+                .errors
+                .push(format!("{node} never answered its final read"));
+        }
+    }
+    verdict
+}
@@ -0,0 +1,173 @@
+// Grow-only counter backed by seq-kv
+// https://fly.io/dist-sys/4/
+//
+// Every node owns a key holding everything that was added through it, so there is
+// never any contention on writes. A read sums our own total with whatever the
+// other nodes last managed to write.
+use std::{collections::HashMap, time::Duration};
+
+use serde_json::json;
+
+use crate::{
+    handler::{Handler, Unhandled},
+    kv::{KvError, KvService},
+    node::Node,
+    protocol::SpecificBodyFields,
+    wal::Record,
+};
+
+// How long to wait before writing our total again after a failed write
+const FLUSH_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+pub struct Counter {
+    // everything added through this node, the source of truth for its key
+    total: usize,
+    // last total we know made it to seq-kv
+    written: usize,
+    writing: bool,
+    // highest total read for every other node, totals only grow so a stale read
+    // never makes the counter go backwards
+    peers: HashMap<String, usize>,
+    reads: HashMap<usize, PendingRead>,
+    read_counter: usize,
+}
+
+// A client read waiting for the totals of the other nodes
+#[derive(Debug)]
+struct PendingRead {
+    client: String,
+    msg_id: Option<usize>,
+    remaining: usize,
+}
+
+impl Counter {
+    // Nothing we restored is known to be in seq-kv, it's written again on init
+    pub(crate) fn restore(&mut self, total: usize) {
+        self.total = total;
+    }
+}
+
+fn key(node: &str) -> String {
+    format!("counter-{node}")
+}
+
+pub struct CounterHandler;
+
+impl Handler for CounterHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|counter: &mut Counter, node| {
+            match body {
+                SpecificBodyFields::Add { delta } => counter.add(node, src, msg_id, delta),
+                SpecificBodyFields::Read { key: None } => counter.read(node, src, msg_id),
+                other => return Err(Unhandled::Passed(other)),
+            }
+            Ok(())
+        })
+    }
+
+    fn init(&self, node: &mut Node) {
+        node.with_state(Counter::flush);
+    }
+}
+
+impl Counter {
+    fn add(&mut self, node: &mut Node, client: String, msg_id: Option<usize>, delta: usize) {
+        self.total += delta;
+        node.persist(Record::Counter { total: self.total });
+        node.reply(client, msg_id, SpecificBodyFields::AddOk);
+        self.flush(node);
+    }
+
+    fn read(&mut self, node: &mut Node, client: String, msg_id: Option<usize>) {
+        let peers: Vec<String> = node
+            .node_ids
+            .iter()
+            .filter(|peer| **peer != node.id)
+            .cloned()
+            .collect();
+        let read_id = self.read_counter;
+        self.read_counter += 1;
+        self.reads.insert(
+            read_id,
+            PendingRead {
+                client,
+                msg_id,
+                remaining: peers.len(),
+            },
+        );
+        if peers.is_empty() {
+            self.complete_read(node, read_id);
+        }
+
+        for peer in peers {
+            node.kv_read(KvService::Seq, key(&peer), move |node, result| {
+                node.with_state(|counter: &mut Counter, node| {
+                    match result {
+                        Ok(total) => {
+                            let known = counter.peers.entry(peer).or_default();
+                            *known = (*known).max(total);
+                        }
+                        // nothing was ever added through that node
+                        Err(KvError::KeyDoesNotExist) => {}
+                        // keep serving what we last saw, it'll catch up after the partition
+                        Err(e) => eprintln!("COULDN'T READ THE TOTAL OF {peer}: {e}"),
+                    }
+                    if let Some(read) = counter.reads.get_mut(&read_id) {
+                        read.remaining -= 1;
+                        if read.remaining == 0 {
+                            counter.complete_read(node, read_id);
+                        }
+                    }
+                })
+            });
+        }
+    }
+
+    fn complete_read(&mut self, node: &mut Node, read_id: usize) {
+        if let Some(read) = self.reads.remove(&read_id) {
+            let value = self.total + self.peers.values().sum::<usize>();
+            node.reply(
+                read.client,
+                read.msg_id,
+                SpecificBodyFields::ReadOk {
+                    messages: None,
+                    value: Some(json!(value)),
+                },
+            );
+        }
+    }
+
+    // Write our total to seq-kv, one write at a time so they can't land out of order
+    fn flush(&mut self, node: &mut Node) {
+        if self.writing || self.written == self.total {
+            return;
+        }
+        self.writing = true;
+        let total = self.total;
+        node.kv_write(KvService::Seq, key(&node.id), total, move |node, result| {
+            node.with_state(|counter: &mut Counter, node| {
+                counter.writing = false;
+                match result {
+                    Ok(()) => {
+                        counter.written = total;
+                        // more may have been added while the write was in flight
+                        counter.flush(node);
+                    }
+                    Err(e) => {
+                        eprintln!("COULDN'T WRITE OUR TOTAL OF {total}: {e}");
+                        node.schedule_once(FLUSH_RETRY_DELAY, |node| {
+                            node.with_state(Counter::flush)
+                        });
+                    }
+                }
+            })
+        });
+    }
+}
@@ -0,0 +1,501 @@
+// A small stand-in for maelstrom to check changes locally without a JVM.
+//
+// Spawns the nodes as child processes, routes the JSON lines they write on stdout
+// to whoever they're addressed to, plays the seq-kv, lin-kv and lww-kv services
+// itself, and acts as the clients of the workload, sending operations at --rate
+// for --time-limit seconds before checking what came back.
+//
+//   cargo run --bin driver -- -w broadcast --bin target/release/alter --node-count 5
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use flydis::{
+    check::{self, Op, Operation, Outcome, Verdict},
+    kv::Store,
+    linearizable::{self, KvOp},
+    protocol::{Body, Message, SpecificBodyFields},
+    rng::Rng,
+    sim,
+};
+use serde_json::json;
+
+// Time given to the nodes to answer init and topology
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+// Time given to the nodes to converge once operations stop, before the final reads
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+// Operations still unanswered after that long are considered lost
+const OPERATION_TIMEOUT: Duration = Duration::from_secs(5);
+// Where the nodes' stderr ends up, one file per node
+const LOG_DIR: &str = "store/driver";
+
+const WORKLOADS: [&str; 5] = ["echo", "unique-ids", "broadcast", "g-counter", "lin-kv"];
+
+const USAGE: &str = "usage: driver -w <echo|unique-ids|broadcast|g-counter|lin-kv> --bin <path> \
+[--node-count <n>] [--rate <ops/s>] [--time-limit <s>] [--seed <n>]";
+
+#[derive(Debug)]
+struct Options {
+    workload: String,
+    bin: String,
+    node_count: usize,
+    rate: f64,
+    time_limit: Duration,
+    seed: u64,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut workload = None;
+    let mut bin = None;
+    let mut node_count = 1;
+    let mut rate = 10.0;
+    let mut time_limit = Duration::from_secs(10);
+    let mut seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+        match arg.as_str() {
+            // so that maelstrom command lines can be pasted as is
+            "test" => {}
+            "-w" | "--workload" => workload = Some(value()?),
+            "--bin" => bin = Some(value()?),
+            "--node-count" => node_count = value()?.parse().map_err(|e| format!("{e}"))?,
+            "--rate" => rate = value()?.parse().map_err(|e| format!("{e}"))?,
+            "--time-limit" => {
+                time_limit = Duration::from_secs_f64(value()?.parse().map_err(|e| format!("{e}"))?)
+            }
+            "--seed" => seed = value()?.parse().map_err(|e| format!("{e}"))?,
+            other => return Err(format!("unknown argument {other}")),
+        }
+    }
+
+    let workload = workload.ok_or("missing --workload")?;
+    if !WORKLOADS.contains(&workload.as_str()) {
+        return Err(format!("unsupported workload {workload}"));
+    }
+    if node_count == 0 || rate <= 0.0 {
+        return Err(String::from("--node-count and --rate must be positive"));
+    }
+    Ok(Options {
+        workload,
+        bin: bin.ok_or("missing --bin")?,
+        node_count,
+        rate,
+        time_limit,
+        seed,
+    })
+}
+
+enum Event {
+    Received(Message),
+    Exited(String),
+}
+
+struct Driver {
+    options: Options,
+    rng: Rng,
+    children: Vec<Child>,
+    stdins: HashMap<String, ChildStdin>,
+    events: Receiver<Event>,
+    // service -> what it holds
+    kv: HashMap<String, Store>,
+    msg_counter: usize,
+    start: Instant,
+    // msg_id -> operation waiting for its reply, along with when it was sent
+    pending: HashMap<usize, (Op, String, Duration)>,
+    history: Vec<Operation>,
+    errors: Vec<String>,
+}
+
+impl Driver {
+    fn spawn(options: Options) -> std::io::Result<Self> {
+        let node_ids: Vec<String> = (0..options.node_count).map(|i| format!("n{i}")).collect();
+        fs::create_dir_all(LOG_DIR)?;
+        let (tx, rx) = mpsc::channel();
+        let mut children = Vec::new();
+        let mut stdins = HashMap::new();
+        for id in &node_ids {
+            let log = File::create(PathBuf::from(LOG_DIR).join(format!("{id}.log")))?;
+            let mut command = Command::new(&options.bin);
+            // the workloads the nodes can't tell apart from the messages alone
+            if matches!(
+                options.workload.as_str(),
+                "broadcast" | "g-counter" | "lin-kv"
+            ) {
+                command.env("FLYDIS_WORKLOAD", &options.workload);
+            }
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(log)
+                .spawn()?;
+            stdins.insert(id.clone(), child.stdin.take().unwrap());
+            read_lines(
+                id.clone(),
+                BufReader::new(child.stdout.take().unwrap()),
+                tx.clone(),
+            );
+            children.push(child);
+        }
+        Ok(Driver {
+            rng: Rng::new(options.seed),
+            options,
+            children,
+            stdins,
+            events: rx,
+            kv: HashMap::new(),
+            msg_counter: 0,
+            start: Instant::now(),
+            pending: HashMap::new(),
+            history: Vec::new(),
+            errors: Vec::new(),
+        })
+    }
+
+    fn node_ids(&self) -> Vec<String> {
+        (0..self.options.node_count)
+            .map(|i| format!("n{i}"))
+            .collect()
+    }
+
+    fn write(&mut self, message: Message) {
+        // the node exited, which was already reported
+        let Some(stdin) = self.stdins.get_mut(&message.dest) else {
+            return;
+        };
+        if let Err(e) = writeln!(stdin, "{}", json!(message)).and_then(|_| stdin.flush()) {
+            self.errors
+                .push(format!("couldn't write to {}: {e}", message.dest));
+        }
+    }
+
+    fn request(&mut self, client: &str, node: &str, body: SpecificBodyFields) -> usize {
+        let msg_id = self.msg_counter;
+        self.msg_counter += 1;
+        self.write(Message {
+            src: client.to_string(),
+            dest: node.to_string(),
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+            },
+        });
+        msg_id
+    }
+
+    fn operation(&mut self, op: Op, node: &str) {
+        let body = match &op {
+            Op::Echo(echo) => SpecificBodyFields::Echo { echo: echo.clone() },
+            Op::Generate => SpecificBodyFields::Generate,
+            Op::Broadcast(value) => SpecificBodyFields::Broadcast {
+                broadcast_message: *value,
+            },
+            Op::Add(delta) => SpecificBodyFields::Add { delta: *delta },
+            Op::Read | Op::FinalRead => SpecificBodyFields::Read { key: None },
+            Op::Kv { key, op } => match op.clone() {
+                KvOp::Read => SpecificBodyFields::Read {
+                    key: Some(key.clone()),
+                },
+                KvOp::Write(value) => SpecificBodyFields::Write {
+                    key: key.clone(),
+                    value,
+                },
+                KvOp::Cas { from, to } => SpecificBodyFields::Cas {
+                    key: key.clone(),
+                    from,
+                    to,
+                    create_if_not_exists: false,
+                },
+            },
+        };
+        let msg_id = self.request("c1", node, body);
+        let invoked = self.start.elapsed();
+        self.pending.insert(msg_id, (op, node.to_string(), invoked));
+    }
+
+    fn random_operation(&mut self, sequence: usize) {
+        let nodes = self.node_ids();
+        let node = &nodes[self.rng.next_u64() as usize % nodes.len()];
+        let op = match self.options.workload.as_str() {
+            "echo" => Op::Echo(format!("Please echo {sequence}")),
+            "unique-ids" => Op::Generate,
+            "broadcast" if self.rng.chance(0.5) => Op::Read,
+            "broadcast" => Op::Broadcast(sequence),
+            "g-counter" if self.rng.chance(0.5) => Op::Read,
+            "g-counter" => Op::Add(self.rng.next_u64() as usize % 5),
+            "lin-kv" => {
+                // few keys and values, so that operations actually run into each other
+                let rng = &mut self.rng;
+                let key = json!(rng.next_u64() % 5);
+                let op = match rng.next_u64() % 3 {
+                    0 => KvOp::Read,
+                    1 => KvOp::Write(json!(rng.next_u64() % 5)),
+                    _ => KvOp::Cas {
+                        from: json!(rng.next_u64() % 5),
+                        to: json!(rng.next_u64() % 5),
+                    },
+                };
+                Op::Kv { key, op }
+            }
+            workload => unreachable!("unsupported workload {workload}"),
+        };
+        self.operation(op, node);
+    }
+
+    // Wait for the next message from a node until `deadline`, returns false once
+    // the deadline is reached
+    fn pump(&mut self, deadline: Instant) -> bool {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.events.recv_timeout(timeout) {
+            Ok(Event::Received(message)) => {
+                self.route(message);
+                true
+            }
+            Ok(Event::Exited(node)) => {
+                self.errors.push(format!("{node} exited"));
+                self.stdins.remove(&node);
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.errors.push(String::from("every node exited"));
+                false
+            }
+        }
+    }
+
+    fn route(&mut self, message: Message) {
+        if self.node_ids().contains(&message.dest) {
+            self.write(message);
+        } else if message.dest.ends_with("-kv") {
+            self.serve_kv(message);
+        } else if let Some(in_reply_to) = message.body.in_reply_to {
+            self.complete(in_reply_to, message);
+        } else {
+            self.errors
+                .push(format!("unexpected message: {}", json!(message)));
+        }
+    }
+
+    fn complete(&mut self, in_reply_to: usize, message: Message) {
+        let Some((op, node, invoked)) = self.pending.remove(&in_reply_to) else {
+            return;
+        };
+        let outcome = match message.body.specific_fields {
+            SpecificBodyFields::Error { code, text } => Outcome::Failed { code, text },
+            body => Outcome::Ok(body),
+        };
+        self.history.push(Operation {
+            op,
+            node,
+            invoked,
+            completed: Some(self.start.elapsed()),
+            outcome,
+        });
+    }
+
+    fn serve_kv(&mut self, message: Message) {
+        let store = self.kv.entry(message.dest.clone()).or_default();
+        let body = store.serve(message.body.specific_fields);
+        let reply = Message {
+            src: message.dest,
+            dest: message.src,
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(self.msg_counter),
+                in_reply_to: message.body.msg_id,
+            },
+        };
+        self.msg_counter += 1;
+        self.write(reply);
+    }
+
+    // Send the requests and wait for their replies
+    fn setup(&mut self, requests: Vec<(String, SpecificBodyFields)>) {
+        let mut waiting: HashSet<usize> = requests
+            .into_iter()
+            .map(|(node, body)| self.request("c0", &node, body))
+            .collect();
+        let deadline = Instant::now() + SETUP_TIMEOUT;
+        while !waiting.is_empty() {
+            match self
+                .events
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(Event::Received(message)) if message.dest == "c0" => {
+                    if let Some(in_reply_to) = message.body.in_reply_to {
+                        waiting.remove(&in_reply_to);
+                    }
+                }
+                Ok(Event::Received(message)) => self.route(message),
+                Ok(Event::Exited(node)) => {
+                    self.errors.push(format!("{node} exited during setup"));
+                    return;
+                }
+                Err(_) => {
+                    self.errors
+                        .push(format!("{} setup requests went unanswered", waiting.len()));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn run(&mut self) {
+        let node_ids = self.node_ids();
+        let init = node_ids
+            .iter()
+            .map(|id| {
+                let body = SpecificBodyFields::Init {
+                    node_id: id.clone(),
+                    node_ids: node_ids.clone(),
+                };
+                (id.clone(), body)
+            })
+            .collect();
+        self.setup(init);
+        if self.options.workload == "broadcast" {
+            let topology = sim::grid_topology(&node_ids);
+            let requests = node_ids
+                .iter()
+                .map(|id| {
+                    let body = SpecificBodyFields::Topology {
+                        topology: topology.clone(),
+                    };
+                    (id.clone(), body)
+                })
+                .collect();
+            self.setup(requests);
+        }
+        if !self.errors.is_empty() {
+            return;
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / self.options.rate);
+        let end = Instant::now() + self.options.time_limit;
+        let mut next = Instant::now();
+        let mut sequence = 0;
+        while next < end {
+            if Instant::now() >= next {
+                self.random_operation(sequence);
+                sequence += 1;
+                next += interval;
+            } else {
+                self.pump(next);
+            }
+        }
+
+        if matches!(self.options.workload.as_str(), "broadcast" | "g-counter") {
+            let settled = Instant::now() + SETTLE_TIME;
+            while self.pump(settled) {}
+            for node in &node_ids {
+                self.operation(Op::FinalRead, node);
+            }
+        }
+
+        let deadline = Instant::now() + OPERATION_TIMEOUT;
+        while !self.pending.is_empty() && self.pump(deadline) {}
+        for (_, (op, node, invoked)) in self.pending.drain() {
+            self.history.push(Operation {
+                op,
+                node,
+                invoked,
+                completed: None,
+                outcome: Outcome::TimedOut,
+            });
+        }
+        self.history.sort_by_key(|operation| operation.invoked);
+    }
+
+    fn check(&self) -> Verdict {
+        let node_ids = self.node_ids();
+        let mut verdict = match self.options.workload.as_str() {
+            "echo" => check::echo(&self.history),
+            "unique-ids" => check::unique_ids(&self.history),
+            "broadcast" => check::broadcast(&self.history, &node_ids),
+            "g-counter" => check::g_counter(&self.history, &node_ids),
+            "lin-kv" => linearizable::lin_kv(&self.history),
+            workload => unreachable!("unsupported workload {workload}"),
+        };
+        verdict.errors.extend(self.errors.iter().cloned());
+        verdict
+    }
+
+    fn shutdown(&mut self) {
+        // closing stdin is enough for the nodes to stop on their own
+        self.stdins.clear();
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn read_lines(node: String, output: impl BufRead + Send + 'static, events: Sender<Event>) {
+    thread::spawn(move || {
+        for line in output.lines().map_while(Result::ok) {
+            match serde_json::from_str::<Message>(&line) {
+                Ok(message) => {
+                    if events.send(Event::Received(message)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => eprintln!("{node} wrote an invalid message ({e}): {line}"),
+            }
+        }
+        let _ = events.send(Event::Exited(node));
+    });
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let seed = options.seed;
+    let mut driver = match Driver::spawn(options) {
+        Ok(driver) => driver,
+        Err(e) => {
+            eprintln!("Couldn't start the nodes: {e}");
+            std::process::exit(2);
+        }
+    };
+    driver.run();
+    driver.shutdown();
+
+    let count = |f: fn(&Outcome) -> bool| driver.history.iter().filter(|o| f(&o.outcome)).count();
+    println!(
+        "{} operations: {} ok, {} failed, {} timed out",
+        driver.history.len(),
+        count(|o| matches!(o, Outcome::Ok(_))),
+        count(|o| matches!(o, Outcome::Failed { .. })),
+        count(|o| matches!(o, Outcome::TimedOut)),
+    );
+    let verdict = driver.check();
+    for note in &verdict.notes {
+        println!("  {note}");
+    }
+    if verdict.is_valid() {
+        println!("Everything looks good! ヽ('ー`)ノ (seed {seed})");
+    } else {
+        for error in &verdict.errors {
+            println!("  {error}");
+        }
+        println!("Analysis invalid! (ﾉಥ益ಥ）ﾉ ┻━┻ (seed {seed}, node logs in {LOG_DIR})");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,25 @@
+// Echo, where every challenge starts
+// https://fly.io/dist-sys/1/
+use crate::{
+    handler::{Handler, Unhandled},
+    node::Node,
+    protocol::SpecificBodyFields,
+};
+
+pub struct EchoHandler;
+
+impl Handler for EchoHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        let SpecificBodyFields::Echo { echo } = body else {
+            return Err(Unhandled::Passed(body));
+        };
+        node.reply(src, msg_id, SpecificBodyFields::EchoOk { echo });
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+// Errors a node answers requests with, as the error codes maelstrom knows about
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+//
+// Handlers return them rather than panicking, and whatever received the request
+// turns them into an `error` reply.
+use std::fmt;
+
+use crate::protocol::SpecificBodyFields;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeError {
+    // 0, we gave up waiting, the request may or may not have happened
+    Timeout(String),
+    // 1
+    NodeNotFound(String),
+    // 10
+    NotSupported(String),
+    // 11, nothing happened and trying again later may work
+    TemporarilyUnavailable(String),
+    // 12
+    MalformedRequest(String),
+    // 13, something went wrong and the request may or may not have happened
+    Crash(String),
+    // 14
+    Abort(String),
+    // 20
+    KeyDoesNotExist(String),
+    // 21
+    KeyAlreadyExists(String),
+    // 22
+    PreconditionFailed(String),
+    // 30
+    TxnConflict(String),
+    // anything else, maelstrom leaves codes from 1000 up to us
+    Other { code: u32, text: String },
+}
+
+impl NodeError {
+    pub fn from_code(code: u32, text: String) -> Self {
+        match code {
+            0 => NodeError::Timeout(text),
+            1 => NodeError::NodeNotFound(text),
+            10 => NodeError::NotSupported(text),
+            11 => NodeError::TemporarilyUnavailable(text),
+            12 => NodeError::MalformedRequest(text),
+            13 => NodeError::Crash(text),
+            14 => NodeError::Abort(text),
+            20 => NodeError::KeyDoesNotExist(text),
+            21 => NodeError::KeyAlreadyExists(text),
+            22 => NodeError::PreconditionFailed(text),
+            30 => NodeError::TxnConflict(text),
+            code => NodeError::Other { code, text },
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            NodeError::Timeout(_) => 0,
+            NodeError::NodeNotFound(_) => 1,
+            NodeError::NotSupported(_) => 10,
+            NodeError::TemporarilyUnavailable(_) => 11,
+            NodeError::MalformedRequest(_) => 12,
+            NodeError::Crash(_) => 13,
+            NodeError::Abort(_) => 14,
+            NodeError::KeyDoesNotExist(_) => 20,
+            NodeError::KeyAlreadyExists(_) => 21,
+            NodeError::PreconditionFailed(_) => 22,
+            NodeError::TxnConflict(_) => 30,
+            NodeError::Other { code, .. } => *code,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            NodeError::Timeout(text)
+            | NodeError::NodeNotFound(text)
+            | NodeError::NotSupported(text)
+            | NodeError::TemporarilyUnavailable(text)
+            | NodeError::MalformedRequest(text)
+            | NodeError::Crash(text)
+            | NodeError::Abort(text)
+            | NodeError::KeyDoesNotExist(text)
+            | NodeError::KeyAlreadyExists(text)
+            | NodeError::PreconditionFailed(text)
+            | NodeError::TxnConflict(text)
+            | NodeError::Other { text, .. } => text,
+        }
+    }
+
+    // Whether the request definitely didn't happen, which maelstrom assumes of
+    // every code but timeouts and crashes
+    pub fn is_definite(&self) -> bool {
+        !matches!(self, NodeError::Timeout(_) | NodeError::Crash(_))
+    }
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {}: {}", self.code(), self.text())
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+impl From<NodeError> for SpecificBodyFields {
+    fn from(error: NodeError) -> Self {
+        SpecificBodyFields::Error {
+            code: error.code(),
+            text: error.text().to_string(),
+        }
+    }
+}
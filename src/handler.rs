@@ -0,0 +1,52 @@
+// What serves requests on a node. Each workload implements it for the requests it
+// serves and is registered with the node when it's created. The node hands every
+// request to its handlers in turn until one takes it, and answers with a
+// not-supported error when none does.
+//
+// Handlers are called with the node itself so they only get &self. Whatever they
+// need to keep lives in the node too, one state per type, see `Node::with_state`.
+use crate::{error::NodeError, node::Node, protocol::SpecificBodyFields};
+
+pub trait Handler {
+    // Handle `body`, or hand it back if it isn't a request for this handler
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled>;
+
+    // Called once init gave the node its id and the list of nodes, to start timers
+    // and the like
+    fn init(&self, _node: &mut Node) {}
+}
+
+// Why a handler didn't handle a request
+#[derive(Debug)]
+pub enum Unhandled {
+    // not one of its requests, the next handler gets it
+    Passed(SpecificBodyFields),
+    // one of its requests, answered with the error
+    Failed(NodeError),
+}
+
+impl From<NodeError> for Unhandled {
+    fn from(error: NodeError) -> Self {
+        Unhandled::Failed(error)
+    }
+}
+
+// What the node answers requests no handler serves with, which for a known type
+// that didn't parse is that it's malformed
+pub fn not_supported(body: &SpecificBodyFields) -> NodeError {
+    match body {
+        SpecificBodyFields::Custom(custom) if custom.is_malformed() => NodeError::MalformedRequest(
+            format!("{} is missing fields or has bad ones", custom.kind),
+        ),
+        SpecificBodyFields::Custom(custom) => {
+            NodeError::NotSupported(format!("{} isn't a type we serve", custom.kind))
+        }
+        body => NodeError::NotSupported(format!("{} isn't served by this node", body.type_name())),
+    }
+}
@@ -0,0 +1,132 @@
+// Unique ids, snowflake style: the time in milliseconds, the index of the node
+// that made the id, and a sequence counting the ids it made within the same
+// millisecond. No two nodes share an index so they can't collide, and a node never
+// hands out the same (millisecond, sequence) twice.
+//
+// The clock is only trusted to go forward. When it goes back, or when the sequence
+// runs out within a millisecond, we carry on from the last millisecond we used
+// rather than waiting for the clock to catch up. Nothing is persisted though, so a
+// node restarted while its clock is behind can repeat the ids it made before.
+//
+// https://en.wikipedia.org/wiki/Snowflake_ID
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::NodeError;
+
+// 2024-01-01, the numeric format only has 41 bits of milliseconds which last until
+// 2093 from there
+const EPOCH_MILLIS: u64 = 1_704_067_200_000;
+const TIMESTAMP_BITS: u32 = 41;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+pub const MAX_NODES: usize = 1 << NODE_BITS;
+const MAX_SEQUENCE: u16 = (1 << SEQUENCE_BITS) - 1;
+
+// Ids order by time first, then node, then sequence, and so do both their formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id {
+    // since the unix epoch
+    millis: u64,
+    node: u16,
+    sequence: u16,
+}
+
+impl Id {
+    // 41 bits of milliseconds since EPOCH_MILLIS, 10 of node and 12 of sequence,
+    // with the sign bit left alone for languages without unsigned integers
+    pub fn numeric(&self) -> u64 {
+        let millis = (self.millis - EPOCH_MILLIS) & ((1 << TIMESTAMP_BITS) - 1);
+        (millis << (NODE_BITS + SEQUENCE_BITS))
+            | (u64::from(self.node) << SEQUENCE_BITS)
+            | u64::from(self.sequence)
+    }
+
+    // Laid out like a UUIDv7: 48 bits of milliseconds since the unix epoch, the
+    // version, the node where the first random bits go, the variant, then the
+    // sequence. The remaining bits would be random, we leave them at zero since the
+    // node and sequence already make the id unique.
+    // https://www.rfc-editor.org/rfc/rfc9562#name-uuid-version-7
+    pub fn sortable(&self) -> u128 {
+        (u128::from(self.millis & ((1 << 48) - 1)) << 80)
+            | (0x7 << 76)
+            | (u128::from(self.node) << 64)
+            | (0b10 << 62)
+            | (u128::from(self.sequence) << (62 - SEQUENCE_BITS))
+    }
+
+    // The sortable format written out as a UUID
+    pub fn uuid(&self) -> String {
+        let hex = format!("{:032x}", self.sortable());
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.numeric())
+    }
+}
+
+#[derive(Debug)]
+pub struct IdGenerator {
+    node: u16,
+    // of the last id we made
+    millis: u64,
+    sequence: u16,
+}
+
+impl IdGenerator {
+    // `node` has to be unique in the cluster, the index of the node in the node ids
+    // maelstrom sends with init is
+    pub fn new(node: usize) -> Result<Self, NodeError> {
+        if node >= MAX_NODES {
+            return Err(NodeError::Crash(format!(
+                "ids only make room for {MAX_NODES} nodes, we're number {}",
+                node + 1
+            )));
+        }
+        Ok(IdGenerator {
+            node: node as u16,
+            millis: 0,
+            sequence: 0,
+        })
+    }
+
+    pub fn generate(&mut self) -> Id {
+        self.generate_at(SystemTime::now())
+    }
+
+    // Make an id as if the clock read `now`
+    pub fn generate_at(&mut self, now: SystemTime) -> Id {
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64)
+            .max(EPOCH_MILLIS);
+        if millis > self.millis {
+            self.millis = millis;
+            self.sequence = 0;
+        } else if self.sequence < MAX_SEQUENCE {
+            // same millisecond, or the clock went back
+            self.sequence += 1;
+        } else {
+            // borrow the next millisecond, the clock will catch up with it
+            self.millis += 1;
+            self.sequence = 0;
+        }
+        Id {
+            millis: self.millis,
+            node: self.node,
+            sequence: self.sequence,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Kafka-style replicated log
+// https://fly.io/dist-sys/5a/
+//
+// Every key is its own append-only log, messages get monotonically increasing
+// offsets and clients commit the offset they processed up to.
+//
+// With several nodes every key is owned by one of them, picked by hashing the key.
+// Sends are forwarded to the owner which is the only one handing out offsets for
+// that key, so they stay unique and gapless, and it then replicates the new entry
+// to everyone else. Committed offsets are replicated to every node the same way.
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    time::Duration,
+};
+
+use crate::{
+    error::NodeError,
+    handler::{Handler, Unhandled},
+    node::{Node, RpcError},
+    protocol::{Body, Message, SpecificBodyFields},
+    wal::Record,
+};
+
+// Most messages returned per key by a single poll
+const POLL_LIMIT: usize = 100;
+// How long to wait before retrying a replication that wasn't acknowledged
+const REPLICATION_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+pub struct Kafka {
+    logs: HashMap<String, Log>,
+    committed: HashMap<String, usize>,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    // offset -> message
+    entries: BTreeMap<usize, usize>,
+}
+
+impl Log {
+    fn append(&mut self, msg: usize) -> usize {
+        let offset = self
+            .entries
+            .last_key_value()
+            .map_or(0, |(offset, _)| offset + 1);
+        self.entries.insert(offset, msg);
+        offset
+    }
+
+    // Replicas may learn about entries out of order, so stop at the first missing
+    // offset rather than let a client skip over it
+    fn read_from(&self, offset: usize) -> Vec<(usize, usize)> {
+        self.entries
+            .range(offset..)
+            .zip(offset..)
+            .take_while(|((offset, _), expected)| **offset == *expected)
+            .take(POLL_LIMIT)
+            .map(|((offset, msg), _)| (*offset, *msg))
+            .collect()
+    }
+}
+
+impl Kafka {
+    fn poll(&self, offsets: &HashMap<String, usize>) -> HashMap<String, Vec<(usize, usize)>> {
+        offsets
+            .iter()
+            .filter_map(|(key, offset)| {
+                let log = self.logs.get(key)?;
+                Some((key.clone(), log.read_from(*offset)))
+            })
+            .collect()
+    }
+
+    // Committed offsets only ever move forward
+    pub(crate) fn commit(&mut self, offsets: HashMap<String, usize>) {
+        for (key, offset) in offsets {
+            let committed = self.committed.entry(key).or_default();
+            *committed = (*committed).max(offset);
+        }
+    }
+
+    // The WAL replay hook, entries replicated from their owner go through it too
+    pub(crate) fn insert_entry(&mut self, key: String, offset: usize, msg: usize) {
+        self.logs
+            .entry(key)
+            .or_default()
+            .entries
+            .insert(offset, msg);
+    }
+
+    fn committed(&self, keys: &[String]) -> HashMap<String, usize> {
+        keys.iter()
+            .filter_map(|key| Some((key.clone(), *self.committed.get(key)?)))
+            .collect()
+    }
+}
+
+pub struct KafkaHandler;
+
+impl Handler for KafkaHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|kafka: &mut Kafka, node| {
+            match body {
+                SpecificBodyFields::Send { key, msg } => kafka.send(node, src, msg_id, key, msg),
+                SpecificBodyFields::Poll { offsets } => {
+                    let msgs = kafka.poll(&offsets);
+                    node.reply(src, msg_id, SpecificBodyFields::PollOk { msgs });
+                }
+                SpecificBodyFields::CommitOffsets { offsets } => {
+                    kafka.commit_offsets(node, src, msg_id, offsets)
+                }
+                SpecificBodyFields::ListCommittedOffsets { keys } => {
+                    let offsets = kafka.committed(&keys);
+                    node.reply(
+                        src,
+                        msg_id,
+                        SpecificBodyFields::ListCommittedOffsetsOk { offsets },
+                    );
+                }
+                SpecificBodyFields::ReplicateLog { key, offset, msg } => {
+                    kafka.replicate_log(node, src, msg_id, key, offset, msg)
+                }
+                other => return Err(Unhandled::Passed(other)),
+            }
+            Ok(())
+        })
+    }
+}
+
+fn owner(node: &Node, key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    node.node_ids
+        .get(hasher.finish() as usize % node.node_ids.len().max(1))
+        .cloned()
+        .unwrap_or_else(|| node.id.clone())
+}
+
+fn peers(node: &Node) -> Vec<String> {
+    node.node_ids
+        .iter()
+        .filter(|peer| **peer != node.id)
+        .cloned()
+        .collect()
+}
+
+impl Kafka {
+    fn send(
+        &mut self,
+        node: &mut Node,
+        client: String,
+        msg_id: Option<usize>,
+        key: String,
+        msg: usize,
+    ) {
+        let owner = owner(node, &key);
+        if owner != node.id {
+            let body = SpecificBodyFields::Send { key, msg };
+            node.rpc(owner, body, move |node, reply| {
+                let answer = match reply {
+                    Ok(Message {
+                        body:
+                            Body {
+                                specific_fields:
+                                    answer @ (SpecificBodyFields::SendOk { .. }
+                                    | SpecificBodyFields::Error { .. }),
+                                ..
+                            },
+                        ..
+                    }) => answer,
+                    // the owner may or may not have appended it, so we can't say
+                    Err(RpcError::Timeout) => {
+                        NodeError::Timeout(String::from("timed out forwarding the send")).into()
+                    }
+                    Ok(reply) => NodeError::Crash(format!(
+                        "the owner of the key answered with {}",
+                        reply.body.specific_fields.type_name()
+                    ))
+                    .into(),
+                };
+                node.reply(client, msg_id, answer);
+            });
+            return;
+        }
+
+        let offset = self.logs.entry(key.clone()).or_default().append(msg);
+        node.persist(Record::KafkaEntry {
+            key: key.clone(),
+            offset,
+            msg,
+        });
+        node.reply(client, msg_id, SpecificBodyFields::SendOk { offset });
+        for peer in peers(node) {
+            replicate_log_entry(node, peer, key.clone(), offset, msg);
+        }
+    }
+
+    fn replicate_log(
+        &mut self,
+        node: &mut Node,
+        owner: String,
+        msg_id: Option<usize>,
+        key: String,
+        offset: usize,
+        msg: usize,
+    ) {
+        node.persist(Record::KafkaEntry {
+            key: key.clone(),
+            offset,
+            msg,
+        });
+        self.insert_entry(key, offset, msg);
+        node.reply(owner, msg_id, SpecificBodyFields::ReplicateLogOk);
+    }
+
+    fn commit_offsets(
+        &mut self,
+        node: &mut Node,
+        client: String,
+        msg_id: Option<usize>,
+        offsets: HashMap<String, usize>,
+    ) {
+        // commits coming from another node are replicas of what a client committed there
+        if !node.node_ids.contains(&client) {
+            for peer in peers(node) {
+                replicate_commit(node, peer, offsets.clone());
+            }
+        }
+        node.persist(Record::KafkaCommit {
+            offsets: offsets.clone(),
+        });
+        self.commit(offsets);
+        node.reply(client, msg_id, SpecificBodyFields::CommitOffsetsOk);
+    }
+}
+
+fn replicate_log_entry(node: &mut Node, peer: String, key: String, offset: usize, msg: usize) {
+    let body = SpecificBodyFields::ReplicateLog {
+        key: key.clone(),
+        offset,
+        msg,
+    };
+    node.rpc(peer.clone(), body, move |node, reply| {
+        if let Err(RpcError::Timeout) = reply {
+            node.schedule_once(REPLICATION_RETRY_DELAY, move |node| {
+                replicate_log_entry(node, peer, key, offset, msg)
+            });
+        }
+    });
+}
+
+fn replicate_commit(node: &mut Node, peer: String, offsets: HashMap<String, usize>) {
+    let body = SpecificBodyFields::CommitOffsets {
+        offsets: offsets.clone(),
+    };
+    node.rpc(peer.clone(), body, move |node, reply| {
+        if let Err(RpcError::Timeout) = reply {
+            node.schedule_once(REPLICATION_RETRY_DELAY, move |node| {
+                replicate_commit(node, peer, offsets)
+            });
+        }
+    });
+}
@@ -0,0 +1,207 @@
+// Client for the key/value services maelstrom runs next to the nodes
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/services.md
+use std::{collections::HashMap, fmt};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+use crate::{
+    node::{Node, RpcError},
+    protocol::{Message, SpecificBodyFields},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvService {
+    // sequentially consistent, reads may be stale
+    Seq,
+    // linearizable
+    Lin,
+    // last write wins, eventually consistent
+    Lww,
+}
+
+impl KvService {
+    pub fn address(self) -> &'static str {
+        match self {
+            KvService::Seq => "seq-kv",
+            KvService::Lin => "lin-kv",
+            KvService::Lww => "lww-kv",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KvError {
+    // the service didn't answer in time, the operation may or may not have happened
+    Timeout,
+    // error code 20
+    KeyDoesNotExist,
+    // error code 22, the value wasn't `from` when attempting a cas
+    PreconditionFailed,
+    // any other error code the service replied with
+    Other { code: u32, text: String },
+    // a reply we can't make sense of, like a value of the wrong type
+    Malformed(String),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::Timeout => write!(f, "timed out"),
+            KvError::KeyDoesNotExist => write!(f, "key does not exist"),
+            KvError::PreconditionFailed => write!(f, "precondition failed"),
+            KvError::Other { code, text } => write!(f, "error {code}: {text}"),
+            KvError::Malformed(reason) => write!(f, "malformed reply: {reason}"),
+        }
+    }
+}
+
+impl From<RpcError> for KvError {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::Timeout => KvError::Timeout,
+        }
+    }
+}
+
+// Turn whatever the service answered into the body of a successful reply
+fn into_reply(reply: Result<Message, RpcError>) -> Result<SpecificBodyFields, KvError> {
+    match reply?.body.specific_fields {
+        SpecificBodyFields::Error { code: 20, .. } => Err(KvError::KeyDoesNotExist),
+        SpecificBodyFields::Error { code: 22, .. } => Err(KvError::PreconditionFailed),
+        SpecificBodyFields::Error { code, text } => Err(KvError::Other { code, text }),
+        fields => Ok(fields),
+    }
+}
+
+impl Node {
+    pub fn kv_read<V: DeserializeOwned>(
+        &mut self,
+        service: KvService,
+        key: impl Serialize,
+        callback: impl FnOnce(&mut Self, Result<V, KvError>) + 'static,
+    ) {
+        let body = SpecificBodyFields::Read {
+            key: Some(json!(key)),
+        };
+        self.rpc(service.address().to_string(), body, |node, reply| {
+            let result = into_reply(reply).and_then(|fields| match fields {
+                SpecificBodyFields::ReadOk {
+                    value: Some(value), ..
+                } => serde_json::from_value(value).map_err(|e| KvError::Malformed(e.to_string())),
+                other => Err(KvError::Malformed(format!(
+                    "expected read_ok, got {other:?}"
+                ))),
+            });
+            callback(node, result)
+        });
+    }
+
+    pub fn kv_write(
+        &mut self,
+        service: KvService,
+        key: impl Serialize,
+        value: impl Serialize,
+        callback: impl FnOnce(&mut Self, Result<(), KvError>) + 'static,
+    ) {
+        let body = SpecificBodyFields::Write {
+            key: json!(key),
+            value: json!(value),
+        };
+        self.rpc(service.address().to_string(), body, |node, reply| {
+            let result = into_reply(reply).and_then(|fields| match fields {
+                SpecificBodyFields::WriteOk => Ok(()),
+                other => Err(KvError::Malformed(format!(
+                    "expected write_ok, got {other:?}"
+                ))),
+            });
+            callback(node, result)
+        });
+    }
+
+    // Set `key` to `to` only if it currently is `from`. When `create_if_not_exists`
+    // is set a missing key is created with `to` instead of failing.
+    pub fn kv_cas(
+        &mut self,
+        service: KvService,
+        key: impl Serialize,
+        from: impl Serialize,
+        to: impl Serialize,
+        create_if_not_exists: bool,
+        callback: impl FnOnce(&mut Self, Result<(), KvError>) + 'static,
+    ) {
+        let body = SpecificBodyFields::Cas {
+            key: json!(key),
+            from: json!(from),
+            to: json!(to),
+            create_if_not_exists,
+        };
+        self.rpc(service.address().to_string(), body, |node, reply| {
+            let result = into_reply(reply).and_then(|fields| match fields {
+                SpecificBodyFields::CasOk => Ok(()),
+                other => Err(KvError::Malformed(format!(
+                    "expected cas_ok, got {other:?}"
+                ))),
+            });
+            callback(node, result)
+        });
+    }
+}
+
+// A service kept in memory, for running nodes without maelstrom. It's
+// linearizable, which is as strong as any of maelstrom's gets.
+#[derive(Debug, Clone, Default)]
+pub struct Store {
+    // keys are compared through their JSON
+    values: HashMap<String, Value>,
+}
+
+impl Store {
+    pub fn get(&self, key: impl Serialize) -> Option<&Value> {
+        self.values.get(&json!(key).to_string())
+    }
+
+    // What the service answers to a request
+    pub fn serve(&mut self, body: SpecificBodyFields) -> SpecificBodyFields {
+        match body {
+            SpecificBodyFields::Read { key: Some(key) } => {
+                match self.values.get(&key.to_string()) {
+                    Some(value) => SpecificBodyFields::ReadOk {
+                        messages: None,
+                        value: Some(value.clone()),
+                    },
+                    None => error(20, "key does not exist"),
+                }
+            }
+            SpecificBodyFields::Write { key, value } => {
+                self.values.insert(key.to_string(), value);
+                SpecificBodyFields::WriteOk
+            }
+            SpecificBodyFields::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get(&key.to_string()) {
+                Some(current) if *current == from => {
+                    self.values.insert(key.to_string(), to);
+                    SpecificBodyFields::CasOk
+                }
+                Some(current) => error(22, &format!("expected {from}, found {current}")),
+                None if create_if_not_exists => {
+                    self.values.insert(key.to_string(), to);
+                    SpecificBodyFields::CasOk
+                }
+                None => error(20, "key does not exist"),
+            },
+            other => error(10, &format!("unsupported by kv: {}", other.type_name())),
+        }
+    }
+}
+
+fn error(code: u32, text: &str) -> SpecificBodyFields {
+    SpecificBodyFields::Error {
+        code,
+        text: text.to_string(),
+    }
+}
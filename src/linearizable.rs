@@ -0,0 +1,489 @@
+// Linearizability checker for histories of read, write and cas on keys, the kind
+// lin-kv promises. It's the Wing & Gong search with Lowe's memoization, the one
+// behind Knossos and Porcupine: operations are linearized one at a time, always
+// picking one that was invoked before every remaining operation returned, and the
+// search backtracks whenever it reaches the return of an operation it couldn't
+// place. Every key is a register of its own so they are checked separately.
+//
+// Operations that timed out may have happened at any point after they were invoked,
+// or never, so they are allowed to take effect or not, whenever.
+//
+// When a key isn't linearizable the history is cut down to the shortest window of
+// time that still isn't, starting from any value the register could have held,
+// which is usually a handful of operations instead of thousands.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{
+    check::{self as checks, Operation, Outcome, Verdict},
+    protocol::SpecificBodyFields,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvOp {
+    Read,
+    Write(Value),
+    Cas { from: Value, to: Value },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KvOutcome {
+    // read_ok
+    Read(Value),
+    // write_ok or cas_ok
+    Ok,
+    KeyDoesNotExist,
+    PreconditionFailed,
+    // timed out, or any error that doesn't say whether it happened
+    Unknown,
+}
+
+// Operations that definitely didn't happen, like ones refused as temporarily
+// unavailable, have no place in the history and should be left out of it
+#[derive(Debug, Clone)]
+pub struct KvOperation {
+    pub client: String,
+    pub key: String,
+    pub op: KvOp,
+    pub invoked: Duration,
+    // ignored for unknown outcomes
+    pub completed: Option<Duration>,
+    pub outcome: KvOutcome,
+}
+
+impl fmt::Display for KvOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match &self.op {
+            KvOp::Read => String::from("read"),
+            KvOp::Write(value) => format!("write {value}"),
+            KvOp::Cas { from, to } => format!("cas {from} -> {to}"),
+        };
+        let outcome = match &self.outcome {
+            KvOutcome::Read(value) => format!("{value}"),
+            KvOutcome::Ok => String::from("ok"),
+            KvOutcome::KeyDoesNotExist => String::from("key does not exist"),
+            KvOutcome::PreconditionFailed => String::from("precondition failed"),
+            KvOutcome::Unknown => String::from("unknown"),
+        };
+        let completed = match (&self.outcome, self.completed) {
+            (KvOutcome::Unknown, _) | (_, None) => String::from("?"),
+            (_, Some(completed)) => format!("{completed:?}"),
+        };
+        write!(
+            f,
+            "[{:?}, {completed}] {} {op} on {}: {outcome}",
+            self.invoked, self.client, self.key
+        )
+    }
+}
+
+// The part of a history of a single key that isn't linearizable
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub key: String,
+    pub from: Duration,
+    pub until: Duration,
+    pub operations: Vec<KvOperation>,
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} isn't linearizable between {:?} and {:?}:",
+            self.key, self.from, self.until
+        )?;
+        for operation in &self.operations {
+            write!(f, "\n    {operation}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn check(history: &[KvOperation]) -> Verdict {
+    let mut verdict = Verdict::default();
+    let mut keys: BTreeMap<&str, Vec<KvOperation>> = BTreeMap::new();
+    for operation in history {
+        keys.entry(&operation.key)
+            .or_default()
+            .push(operation.clone());
+    }
+    for operations in keys.values() {
+        match check_key(operations) {
+            Ok(()) => {}
+            Err(KeyError::Malformed(operation)) => verdict
+                .errors
+                .push(format!("{operation} isn't a possible outcome")),
+            Err(KeyError::NotLinearizable(window)) => verdict.errors.push(window.to_string()),
+        }
+    }
+    verdict.notes.push(format!(
+        "{} operations on {} keys",
+        history.len(),
+        keys.len()
+    ));
+    verdict
+}
+
+// The lin-kv workload, as recorded by the driver or a simulation
+pub fn lin_kv(history: &[Operation]) -> Verdict {
+    let mut unavailable = 0;
+    let mut failed = Vec::new();
+    let operations: Vec<KvOperation> = history
+        .iter()
+        .filter_map(|operation| {
+            let checks::Op::Kv { key, op } = &operation.op else {
+                return None;
+            };
+            let outcome = match &operation.outcome {
+                Outcome::Ok(SpecificBodyFields::ReadOk {
+                    value: Some(value), ..
+                }) => KvOutcome::Read(value.clone()),
+                Outcome::Ok(_) => KvOutcome::Ok,
+                Outcome::Failed { code: 20, .. } => KvOutcome::KeyDoesNotExist,
+                Outcome::Failed { code: 22, .. } => KvOutcome::PreconditionFailed,
+                // refused, so it definitely didn't happen
+                Outcome::Failed { code: 11, .. } => {
+                    unavailable += 1;
+                    return None;
+                }
+                // timed out, on our side or between the nodes
+                Outcome::Failed { code: 0, .. } | Outcome::TimedOut => KvOutcome::Unknown,
+                Outcome::Failed { code, text } => {
+                    failed.push(format!("{operation} failed with {code}: {text}"));
+                    return None;
+                }
+            };
+            Some(KvOperation {
+                client: operation.node.clone(),
+                key: key.to_string(),
+                op: op.clone(),
+                invoked: operation.invoked,
+                completed: operation.completed,
+                outcome,
+            })
+        })
+        .collect();
+    let mut verdict = check(&operations);
+    verdict.errors.extend(failed);
+    verdict
+        .notes
+        .push(format!("{unavailable} operations refused as unavailable"));
+    verdict
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    // an outcome the operation can't have, like a write failing its precondition
+    Malformed(Box<KvOperation>),
+    NotLinearizable(Window),
+}
+
+// Check the operations on a single key
+pub fn check_key(operations: &[KvOperation]) -> Result<(), KeyError> {
+    let mut values = Values::default();
+    let mut ops = Vec::new();
+    for (index, operation) in operations.iter().enumerate() {
+        let transition = values
+            .transition(operation)
+            .ok_or_else(|| KeyError::Malformed(Box::new(operation.clone())))?;
+        if let Some(transition) = transition {
+            let ret = match operation.outcome {
+                KvOutcome::Unknown => None,
+                _ => operation.completed,
+            };
+            ops.push(Op {
+                call: operation.invoked,
+                ret,
+                transition,
+                index,
+            });
+        }
+    }
+    if linearizable(&ops, &[None]) {
+        return Ok(());
+    }
+    let window = minimal_window(operations, &ops, &values);
+    Err(KeyError::NotLinearizable(window))
+}
+
+type State = Option<usize>;
+
+// What an operation does to the register, with values interned so that states
+// are cheap to copy and hash
+#[derive(Debug, Clone, Copy)]
+enum Transition {
+    // the register must hold this
+    Observe(State),
+    Write(usize),
+    // may or may not have happened
+    MaybeWrite(usize),
+    CasOk { from: usize, to: usize },
+    CasFailed { from: usize },
+    CasMaybe { from: usize, to: usize },
+}
+
+impl Transition {
+    // The states the register can be in after this, if it can happen at all
+    fn step(self, state: State) -> [Option<State>; 2] {
+        match self {
+            Transition::Observe(expected) => [(state == expected).then_some(state), None],
+            Transition::Write(value) => [Some(Some(value)), None],
+            Transition::MaybeWrite(value) => [Some(Some(value)), Some(state)],
+            Transition::CasOk { from, to } => [(state == Some(from)).then_some(Some(to)), None],
+            Transition::CasFailed { from } => [
+                (state.is_some() && state != Some(from)).then_some(state),
+                None,
+            ],
+            Transition::CasMaybe { from, to } if state == Some(from) => {
+                [Some(Some(to)), Some(state)]
+            }
+            Transition::CasMaybe { .. } => [Some(state), None],
+        }
+    }
+
+    // What's left of it when we don't know how it went, because the history is cut
+    // before it returned or after it was invoked. Operations that can't change the
+    // register are dropped altogether.
+    fn uncertain(self) -> Option<Transition> {
+        match self {
+            Transition::Observe(_) | Transition::CasFailed { .. } => None,
+            Transition::Write(value) | Transition::MaybeWrite(value) => {
+                Some(Transition::MaybeWrite(value))
+            }
+            Transition::CasOk { from, to } | Transition::CasMaybe { from, to } => {
+                Some(Transition::CasMaybe { from, to })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    call: Duration,
+    // None when it may return whenever, or never
+    ret: Option<Duration>,
+    transition: Transition,
+    // in the original history
+    index: usize,
+}
+
+#[derive(Default)]
+struct Values {
+    ids: HashMap<String, usize>,
+    // the ones something tried to put in the register
+    written: BTreeSet<usize>,
+}
+
+impl Values {
+    fn id(&mut self, value: &Value) -> usize {
+        let next = self.ids.len();
+        *self.ids.entry(value.to_string()).or_insert(next)
+    }
+
+    fn written(&mut self, value: &Value) -> usize {
+        let id = self.id(value);
+        self.written.insert(id);
+        id
+    }
+
+    // None for combinations that make no sense, Some(None) for operations that
+    // can't tell anything about the register
+    fn transition(&mut self, operation: &KvOperation) -> Option<Option<Transition>> {
+        let transition = match (&operation.op, &operation.outcome) {
+            (KvOp::Read, KvOutcome::Read(value)) => Transition::Observe(Some(self.id(value))),
+            (KvOp::Read | KvOp::Cas { .. }, KvOutcome::KeyDoesNotExist) => {
+                Transition::Observe(None)
+            }
+            (KvOp::Read, KvOutcome::Unknown) => return Some(None),
+            (KvOp::Write(value), KvOutcome::Ok) => Transition::Write(self.written(value)),
+            (KvOp::Write(value), KvOutcome::Unknown) => Transition::MaybeWrite(self.written(value)),
+            (KvOp::Cas { from, to }, KvOutcome::Ok) => Transition::CasOk {
+                from: self.id(from),
+                to: self.written(to),
+            },
+            (KvOp::Cas { from, .. }, KvOutcome::PreconditionFailed) => Transition::CasFailed {
+                from: self.id(from),
+            },
+            (KvOp::Cas { from, to }, KvOutcome::Unknown) => Transition::CasMaybe {
+                from: self.id(from),
+                to: self.written(to),
+            },
+            _ => return None,
+        };
+        Some(Some(transition))
+    }
+
+    // Every state the register could ever be in
+    fn states(&self) -> Vec<State> {
+        std::iter::once(None)
+            .chain(self.written.iter().copied().map(Some))
+            .collect()
+    }
+}
+
+// Whether the operations can be linearized starting from any of `initial`
+fn linearizable(ops: &[Op], initial: &[State]) -> bool {
+    initial.iter().any(|state| search(ops, *state))
+}
+
+fn search(ops: &[Op], initial: State) -> bool {
+    // every call and return, in order, returns of operations that may never
+    // return come last, and calls come before returns happening at the same time
+    let mut entries: Vec<(Duration, bool, usize)> = Vec::with_capacity(ops.len() * 2);
+    for (i, op) in ops.iter().enumerate() {
+        entries.push((op.call, false, i));
+        entries.push((op.ret.unwrap_or(Duration::MAX), true, i));
+    }
+    entries.sort();
+
+    // doubly linked list over the entries, `head` being both its start and end
+    let head = entries.len();
+    let mut next: Vec<usize> = (1..=head).chain([0]).collect();
+    let mut prev: Vec<usize> = std::iter::once(head).chain(0..head).collect();
+    let mut returns = vec![0; ops.len()];
+    for (i, (_, is_return, op)) in entries.iter().enumerate() {
+        if *is_return {
+            returns[*op] = i;
+        }
+    }
+    let unlink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        next[prev[i]] = next[i];
+        prev[next[i]] = prev[i];
+    };
+    let relink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        next[prev[i]] = i;
+        prev[next[i]] = i;
+    };
+
+    let mut linearized = vec![0u64; ops.len().div_ceil(64)];
+    let mut cache: HashSet<(Vec<u64>, State)> = HashSet::new();
+    // the calls linearized so far, with the state before them and the alternative
+    // they went with
+    let mut calls: Vec<(usize, State, usize)> = Vec::new();
+    let mut state = initial;
+    let mut entry = next[head];
+    // first alternative to try for `entry`
+    let mut alternative = 0;
+
+    while next[head] != head {
+        let (_, is_return, op) = entries[entry];
+        if !is_return {
+            let outcomes = ops[op].transition.step(state);
+            let mut lifted = false;
+            for (i, outcome) in outcomes.iter().enumerate().skip(alternative) {
+                let Some(new_state) = *outcome else { continue };
+                linearized[op / 64] |= 1 << (op % 64);
+                if cache.insert((linearized.clone(), new_state)) {
+                    calls.push((entry, state, i));
+                    state = new_state;
+                    unlink(&mut next, &mut prev, entry);
+                    unlink(&mut next, &mut prev, returns[op]);
+                    lifted = true;
+                    break;
+                }
+                linearized[op / 64] &= !(1 << (op % 64));
+            }
+            alternative = 0;
+            entry = if lifted { next[head] } else { next[entry] };
+        } else {
+            // an operation returned before we found a place for it, undo the last
+            // call and try what comes after it instead
+            let Some((call, previous, tried)) = calls.pop() else {
+                return false;
+            };
+            let op = entries[call].2;
+            linearized[op / 64] &= !(1 << (op % 64));
+            state = previous;
+            relink(&mut next, &mut prev, returns[op]);
+            relink(&mut next, &mut prev, call);
+            entry = call;
+            alternative = tried + 1;
+            if alternative >= 2 {
+                alternative = 0;
+                entry = next[call];
+            }
+        }
+    }
+    true
+}
+
+// Cut the history down to the shortest window that still isn't linearizable.
+// Keeping only what was invoked before some point is a history of its own where
+// whatever hadn't returned yet is uncertain, and the earliest such point where it
+// fails is where the window ends. The window then starts as late as it can while
+// still failing whatever the register held at that point, with operations that
+// were running across its start allowed to have happened before it.
+fn minimal_window(operations: &[KvOperation], ops: &[Op], values: &Values) -> Window {
+    let prefix = |until: Duration| -> Vec<Op> {
+        ops.iter()
+            .filter(|op| op.call <= until)
+            .filter_map(|op| match op.ret {
+                Some(ret) if ret <= until => Some(*op),
+                _ => op.transition.uncertain().map(|transition| Op {
+                    ret: None,
+                    transition,
+                    ..*op
+                }),
+            })
+            .collect()
+    };
+    let mut ends: Vec<Duration> = ops.iter().filter_map(|op| op.ret).collect();
+    ends.sort();
+    ends.dedup();
+    let end = first_failing(&ends, |until| !linearizable(&prefix(until), &[None]))
+        .unwrap_or(Duration::MAX);
+    let ops = prefix(end);
+
+    let suffix = |from: Duration| -> Vec<Op> {
+        ops.iter()
+            .filter_map(|op| {
+                if op.call >= from {
+                    Some(*op)
+                } else if op.ret.is_none_or(|ret| ret >= from) {
+                    op.transition
+                        .uncertain()
+                        .map(|transition| Op { transition, ..*op })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    };
+    let states = values.states();
+    let mut starts: Vec<Duration> = ops.iter().map(|op| op.call).collect();
+    starts.sort();
+    starts.dedup();
+    // the latest start that still fails, searching from the end
+    starts.reverse();
+    let (start, window) = match first_failing(&starts, |from| !linearizable(&suffix(from), &states))
+    {
+        Some(start) => (start, suffix(start)),
+        // it only fails because the register starts out empty
+        None => (Duration::ZERO, ops.clone()),
+    };
+
+    let mut indices: Vec<usize> = window.iter().map(|op| op.index).collect();
+    indices.sort();
+    Window {
+        key: operations
+            .first()
+            .map(|o| o.key.clone())
+            .unwrap_or_default(),
+        from: start,
+        until: end,
+        operations: indices.into_iter().map(|i| operations[i].clone()).collect(),
+    }
+}
+
+// The first point for which `fails` holds, given that it keeps holding for every
+// point after it
+fn first_failing(points: &[Duration], fails: impl Fn(Duration) -> bool) -> Option<Duration> {
+    let first = points.partition_point(|point| !fails(*point));
+    points.get(first).copied()
+}
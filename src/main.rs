@@ -1,36 +1,100 @@
-use flydis::*;
-use std::io::BufRead;
+// The first take at the challenges, kept around as the `node` binary. It floods
+// every new broadcast value to its neighbours along with everything it ever
+// propagated, where alter batches and acknowledges them.
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufReader},
+    rc::Rc,
+};
 
-fn main() {
-    let mut me = Node::new();
+use flydis::{
+    echo::EchoHandler,
+    handler::{Handler, Unhandled},
+    node::Node,
+    protocol::SpecificBodyFields,
+    runtime,
+    unique_ids::UniqueIdsHandler,
+};
 
-    for line in me.ears.lock().lines() {
-        let line = line.expect("Line is correctly read");
-        eprintln!("Received input: {}", line);
+struct Flood;
 
-        let message: Message = serde_json::from_str(&line).expect("Line is correctly deserialized");
-        eprintln!("Deserialized input: {:?}", message);
+#[derive(Default)]
+struct FloodState {
+    messages: HashSet<usize>,
+    topo: HashMap<String, Vec<String>>,
+    // (neighbour, value) for everything we propagated
+    propagate_list: HashSet<(String, usize)>,
+}
+
+impl Handler for Flood {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|state: &mut FloodState, node| flood(state, node, src, msg_id, body))
+    }
+}
 
-        match message.body.r#type {
-            Type::Init => me.handle_init(message),
-            Type::Echo => me.handle_echo(message),
-            Type::Generate => me.handle_generate(message),
-            Type::Broadcast => me.handle_broadcast(message),
-            Type::Read => me.handle_read(message),
-            Type::Topology => me.handle_topology(message),
-            // Type::BroadcastOk => me.handle_broadcast_ok(message),
-            r#Type::InitOk
-            | r#Type::EchoOk
-            | r#Type::GenerateOk
-            | r#Type::ReadOk
-            | r#Type::TopologyOk => {
-                eprintln!("Unimplemented message type: {:?}", message.body.r#type);
-                panic!();
+fn flood(
+    state: &mut FloodState,
+    node: &mut Node,
+    src: String,
+    msg_id: Option<usize>,
+    body: SpecificBodyFields,
+) -> Result<(), Unhandled> {
+    match body {
+        SpecificBodyFields::Broadcast { broadcast_message } => {
+            node.reply(src.clone(), msg_id, SpecificBodyFields::BroadcastOk);
+            // a value we already had went around already, passing it on again
+            // would never end now that our neighbours answer
+            if !state.messages.insert(broadcast_message) {
+                return Ok(());
             }
-            _ => {
-                eprintln!("Unknown message type: {:?}", message.body.r#type);
-                panic!();
+            // without a topology yet we keep it to ourselves
+            let neighbors = state.topo.get(node.id()).cloned().unwrap_or_default();
+
+            // broadcast to every neighboring node except for the one who sent
+            for neighbor in neighbors {
+                if neighbor != src {
+                    state.propagate_list.insert((neighbor, broadcast_message));
+                }
+            }
+            // their broadcast_ok answers nothing we wait for, the node drops them
+            for (neighbor, value) in &state.propagate_list {
+                node.reply(
+                    neighbor.clone(),
+                    None,
+                    SpecificBodyFields::Broadcast {
+                        broadcast_message: *value,
+                    },
+                );
             }
-        };
+        }
+        SpecificBodyFields::Read { key: None } => node.reply(
+            src,
+            msg_id,
+            SpecificBodyFields::ReadOk {
+                messages: Some(state.messages.clone()),
+                value: None,
+            },
+        ),
+        SpecificBodyFields::Topology { topology } => {
+            state.topo = topology;
+            node.reply(src, msg_id, SpecificBodyFields::TopologyOk);
+        }
+        other => return Err(Unhandled::Passed(other)),
     }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let node = Node::with_handlers(vec![
+        Rc::new(EchoHandler),
+        Rc::new(UniqueIdsHandler),
+        Rc::new(Flood),
+    ]);
+    runtime::run(node, BufReader::new(io::stdin()), io::stdout())
 }
@@ -0,0 +1,386 @@
+// The node itself, as a state machine that never does any IO. It's fed the messages
+// it receives and the timers it asked for along with the current time, and hands
+// back what it wants sent and the timers it wants set, leaving it to whatever
+// drives it to actually talk to the outside world.
+use std::{
+    any::{self, Any, TypeId},
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::{
+    error::NodeError,
+    handler::{self, Handler, Unhandled},
+    protocol::{Body, Message, SpecificBodyFields},
+    wal::Wal,
+};
+
+// Default time a node to node request waits for its reply
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub type TimerId = usize;
+
+#[derive(Debug)]
+pub enum RpcError {
+    Timeout,
+}
+
+type RpcCallback = Box<dyn FnOnce(&mut Node, Result<Message, RpcError>)>;
+
+// A request waiting for the message whose in_reply_to matches its msg_id
+struct PendingRpc {
+    callback: RpcCallback,
+    timeout: TimerId,
+}
+
+// Callbacks run with the node itself, one-shot timers are wrapped so that they are
+// only ever called once
+type TimerCallback = Box<dyn FnMut(&mut Node)>;
+
+struct Timer {
+    // rescheduled after every run when set
+    every: Option<Duration>,
+    callback: TimerCallback,
+}
+
+// What the node wants done once it's handled an input
+#[derive(Debug)]
+pub enum Output {
+    Send(Message),
+    // call `Node::fire` with `id` once `deadline` is reached
+    Timer { id: TimerId, deadline: Instant },
+}
+
+pub struct Node {
+    pub(crate) id: String,
+    pub(crate) node_ids: Vec<String>,
+    // time of the input being handled
+    pub(crate) now: Instant,
+    // everything produced while handling the current input
+    outbox: Vec<Output>,
+    message_counter: usize,
+    // whatever serves requests, tried in order
+    handlers: Vec<Rc<dyn Handler>>,
+    // whatever the handlers keep, one of every type, see `with_state`
+    states: HashMap<TypeId, Option<Box<dyn Any>>>,
+    // outgoing requests keyed by their msg_id
+    pending_rpcs: HashMap<usize, PendingRpc>,
+    timers: HashMap<TimerId, Timer>,
+    timer_counter: TimerId,
+    // where changes are logged, if anywhere
+    pub(crate) wal: Option<Wal>,
+}
+
+impl Node {
+    // A node serving only what `handlers` serve, the first one serving a request
+    // gets it
+    pub fn with_handlers(handlers: Vec<Rc<dyn Handler>>) -> Self {
+        Self {
+            id: String::from("NO_ID"),
+            node_ids: Vec::new(),
+            now: Instant::now(),
+            outbox: Vec::new(),
+            message_counter: 0,
+            handlers,
+            states: HashMap::new(),
+            pending_rpcs: HashMap::new(),
+            timers: HashMap::new(),
+            timer_counter: 0,
+            wal: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // Our place in the list of nodes init gave us
+    pub fn index(&self) -> usize {
+        self.node_ids
+            .iter()
+            .position(|id| *id == self.id)
+            .unwrap_or_default()
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    // Serve the requests `handler` serves, after the ones already registered
+    pub fn register(&mut self, handler: impl Handler + 'static) {
+        self.handlers.push(Rc::new(handler));
+    }
+
+    // Run `f` with the state of type `S` kept for the handlers, which starts out as
+    // its default. It's taken out of the node for the duration of the call so that
+    // `f` is free to use the node too, short of asking for the same state again.
+    pub fn with_state<S: Default + 'static, R>(
+        &mut self,
+        f: impl FnOnce(&mut S, &mut Self) -> R,
+    ) -> R {
+        let mut state = self
+            .states
+            .entry(TypeId::of::<S>())
+            .or_insert_with(|| Some(Box::new(S::default())))
+            .take()
+            .unwrap_or_else(|| panic!("{} is already in use", any::type_name::<S>()));
+        let result = f(
+            state
+                .downcast_mut()
+                .expect("states are keyed by their type"),
+            self,
+        );
+        self.states.insert(TypeId::of::<S>(), Some(state));
+        result
+    }
+
+    // Handle a message received at `now`
+    pub fn handle(&mut self, message: Message, now: Instant) -> Vec<Output> {
+        self.now = now;
+        self.handle_message(message);
+        std::mem::take(&mut self.outbox)
+    }
+
+    // Run a timer the node asked for, timers that were cancelled in the meantime
+    // are ignored
+    pub fn fire(&mut self, id: TimerId, now: Instant) -> Vec<Output> {
+        self.now = now;
+        // the callback is taken out for the duration of the call so it's free to
+        // schedule or cancel timers, including its own
+        if let Some(timer) = self.timers.get_mut(&id) {
+            let mut callback = std::mem::replace(&mut timer.callback, Box::new(|_| {}));
+            callback(self);
+            // a timer missing at this point was cancelled from its own callback
+            if let Some(timer) = self.timers.get_mut(&id) {
+                match timer.every {
+                    Some(every) => {
+                        timer.callback = callback;
+                        self.outbox.push(Output::Timer {
+                            id,
+                            deadline: now + every,
+                        });
+                    }
+                    None => {
+                        self.timers.remove(&id);
+                    }
+                }
+            }
+        }
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn schedule(
+        &mut self,
+        delay: Duration,
+        every: Option<Duration>,
+        callback: TimerCallback,
+    ) -> TimerId {
+        let id = self.timer_counter;
+        self.timer_counter += 1;
+        self.timers.insert(id, Timer { every, callback });
+        self.outbox.push(Output::Timer {
+            id,
+            deadline: self.now + delay,
+        });
+        id
+    }
+
+    // Run `callback` once after `delay`
+    pub fn schedule_once(
+        &mut self,
+        delay: Duration,
+        callback: impl FnOnce(&mut Self) + 'static,
+    ) -> TimerId {
+        let mut callback = Some(callback);
+        self.schedule(
+            delay,
+            None,
+            Box::new(move |node| {
+                if let Some(callback) = callback.take() {
+                    callback(node)
+                }
+            }),
+        )
+    }
+
+    // Run `callback` every `period` until the timer is cancelled
+    pub fn schedule_every(
+        &mut self,
+        period: Duration,
+        callback: impl FnMut(&mut Self) + 'static,
+    ) -> TimerId {
+        self.schedule(period, Some(period), Box::new(callback))
+    }
+
+    // Returns false if the timer already fired or was cancelled
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    fn send(&mut self, message: Message) {
+        eprintln!(
+            "SENT {}: {}",
+            message.body.specific_fields.type_name(),
+            json!(message)
+        );
+        self.outbox.push(Output::Send(message));
+    }
+
+    pub fn reply(&mut self, dest: String, in_reply_to: Option<usize>, body: SpecificBodyFields) {
+        let answer = Message {
+            src: self.id.clone(),
+            dest,
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(self.message_counter),
+                in_reply_to,
+            },
+        };
+        self.message_counter += 1;
+        self.send(answer);
+    }
+
+    // Send `body` to `dest` and call `callback` with the reply, or with a timeout
+    // error if none arrives within RPC_TIMEOUT
+    pub fn rpc(
+        &mut self,
+        dest: String,
+        body: SpecificBodyFields,
+        callback: impl FnOnce(&mut Self, Result<Message, RpcError>) + 'static,
+    ) -> usize {
+        self.rpc_with_timeout(dest, body, RPC_TIMEOUT, callback)
+    }
+
+    pub fn rpc_with_timeout(
+        &mut self,
+        dest: String,
+        body: SpecificBodyFields,
+        timeout: Duration,
+        callback: impl FnOnce(&mut Self, Result<Message, RpcError>) + 'static,
+    ) -> usize {
+        let msg_id = self.message_counter;
+        self.message_counter += 1;
+        self.send(Message {
+            src: self.id.clone(),
+            dest,
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+            },
+        });
+
+        let timeout = self.schedule_once(timeout, move |node| {
+            if let Some(pending) = node.pending_rpcs.remove(&msg_id) {
+                (pending.callback)(node, Err(RpcError::Timeout));
+            }
+        });
+        self.pending_rpcs.insert(
+            msg_id,
+            PendingRpc {
+                callback: Box::new(callback),
+                timeout,
+            },
+        );
+        msg_id
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        eprintln!(
+            "RECEIVED {}: {}",
+            message.body.specific_fields.type_name(),
+            json!(message)
+        );
+        // Every reply goes to the callback of the request it answers, and since we
+        // never answer a reply anything left over is late and can be dropped
+        if let Some(in_reply_to) = message.body.in_reply_to {
+            match self.pending_rpcs.remove(&in_reply_to) {
+                Some(pending) => {
+                    self.cancel_timer(pending.timeout);
+                    (pending.callback)(self, Ok(message));
+                }
+                None => eprintln!("DROPPED LATE REPLY TO {in_reply_to}"),
+            }
+            return;
+        }
+        let (src, msg_id) = (message.src, message.body.msg_id);
+        let kind = message.body.specific_fields.type_name();
+        if let Err(error) = self.handle_request(src.clone(), msg_id, message.body.specific_fields) {
+            eprintln!("FAILED {kind} FROM {src}: {error}");
+            self.reply(src, msg_id, error.into());
+        }
+    }
+
+    // Answer a request that couldn't be handled at all, like one that didn't parse
+    pub fn refuse(
+        &mut self,
+        dest: String,
+        in_reply_to: Option<usize>,
+        error: NodeError,
+        now: Instant,
+    ) -> Vec<Output> {
+        self.now = now;
+        self.reply(dest, in_reply_to, error.into());
+        std::mem::take(&mut self.outbox)
+    }
+
+    // Do something on the node's own initiative at `now`, like what the code it's
+    // embedded in asks of it
+    pub fn act(&mut self, now: Instant, action: impl FnOnce(&mut Self)) -> Vec<Output> {
+        self.now = now;
+        action(self);
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn handle_request(
+        &mut self,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), NodeError> {
+        // replies are all matched with their request before we get here, so any
+        // left has no in_reply_to
+        match body {
+            SpecificBodyFields::Init { node_id, node_ids } => {
+                if self.id != "NO_ID" {
+                    return Err(NodeError::MalformedRequest(format!(
+                        "already initialized as {}",
+                        self.id
+                    )));
+                }
+                if !node_ids.contains(&node_id) {
+                    return Err(NodeError::MalformedRequest(format!(
+                        "{node_id} isn't one of the node ids {node_ids:?}"
+                    )));
+                }
+                self.id = node_id;
+                self.node_ids = node_ids;
+                for handler in self.handlers.clone() {
+                    handler.init(self);
+                }
+                if let Some(period) = self.wal.as_ref().and_then(Wal::sync_period) {
+                    self.schedule_every(period, |node| node.sync_wal());
+                }
+                self.reply(src, msg_id, SpecificBodyFields::InitOk);
+            }
+            SpecificBodyFields::Error { code, text } => {
+                eprintln!("UNSOLICITED ERROR {code} FROM {src}: {text}")
+            }
+            mut body => {
+                // cloned out so the handlers are free to borrow the node mutably
+                for handler in self.handlers.clone() {
+                    match handler.handle(self, src.clone(), msg_id, body) {
+                        Ok(()) => return Ok(()),
+                        Err(Unhandled::Failed(error)) => return Err(error),
+                        Err(Unhandled::Passed(passed)) => body = passed,
+                    }
+                }
+                return Err(handler::not_supported(&body));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+// The messages nodes exchange with maelstrom, its clients and each other
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message {
+    pub src: String,
+    pub dest: String,
+    pub body: Body,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Body {
+    #[serde(flatten)]
+    pub specific_fields: SpecificBodyFields,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<usize>,
+}
+
+// [op, key, value] where op is "r" or "w", reads are sent with a null value
+pub type TxnOperation = (String, usize, Option<usize>);
+
+// A write made by a transaction, as replicated to the other nodes. Versions are
+// compared as (lamport clock, node id) to pick the last writer of a key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VersionedWrite {
+    pub key: usize,
+    pub value: usize,
+    pub version: (usize, String),
+}
+
+// An entry of the raft log, carrying the client request to apply once it's
+// committed. The entry every log starts with carries none, and neither do the
+// no-ops leaders start their terms with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub term: usize,
+    pub request: Option<Message>,
+}
+
+// Declares SpecificBodyFields along with the name of every variant, so that the
+// types we know are only ever listed once, here. Every variant gets a Custom one
+// after it, see below.
+macro_rules! body_fields {
+    (
+        $(#[$attr:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$variant_attr:meta])*
+                $variant:ident $({ $($fields:tt)* })?,
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        pub enum $name {
+            $(
+                $(#[$variant_attr])*
+                $variant $({ $($fields)* })?,
+            )*
+            // any other type, see CustomBody. Known types missing fields end up here
+            // too, see Custom::is_malformed.
+            #[serde(untagged)]
+            Custom(Custom),
+        }
+
+        // The variants above but Custom
+        const VARIANTS: &[&str] = &[$(stringify!($variant)),*];
+
+        impl $name {
+            fn variant(&self) -> &'static str {
+                match self {
+                    $($name::$variant { .. } => stringify!($variant),)*
+                    $name::Custom(_) => "Custom",
+                }
+            }
+        }
+    };
+}
+
+body_fields! {
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    // internally tagging this enum allows to match the maelstrom protocol specs
+    // https://serde.rs/enum-representations.html
+    #[serde(tag = "type")]
+    #[serde(rename_all = "snake_case")]
+    pub enum SpecificBodyFields {
+        Init {
+            node_id: String,
+            node_ids: Vec<String>,
+        },
+        InitOk,
+        Echo {
+            echo: String,
+        },
+        EchoOk {
+            echo: String,
+        },
+        Generate,
+        GenerateOk {
+            id: String,
+        },
+        Broadcast {
+            #[serde(rename = "message")]
+            broadcast_message: usize,
+        },
+        BroadcastOk,
+        // the broadcast workload reads everything, the kv services read a single key
+        Read {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            key: Option<Value>,
+        },
+        ReadOk {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            messages: Option<HashSet<usize>>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            value: Option<Value>,
+        },
+        Topology {
+            topology: HashMap<String, Vec<String>>,
+        },
+        TopologyOk,
+        MultiBroadcast {
+            messages: HashSet<usize>,
+        },
+        MultiBroadcastOk,
+        Write {
+            key: Value,
+            value: Value,
+        },
+        WriteOk,
+        Cas {
+            key: Value,
+            from: Value,
+            to: Value,
+            #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+            create_if_not_exists: bool,
+        },
+        CasOk,
+        Add {
+            delta: usize,
+        },
+        AddOk,
+        Send {
+            key: String,
+            msg: usize,
+        },
+        SendOk {
+            offset: usize,
+        },
+        Poll {
+            offsets: HashMap<String, usize>,
+        },
+        PollOk {
+            // key -> [offset, message] pairs
+            msgs: HashMap<String, Vec<(usize, usize)>>,
+        },
+        CommitOffsets {
+            offsets: HashMap<String, usize>,
+        },
+        CommitOffsetsOk,
+        ListCommittedOffsets {
+            keys: Vec<String>,
+        },
+        ListCommittedOffsetsOk {
+            offsets: HashMap<String, usize>,
+        },
+        // sent by the owner of a kafka key to the other nodes for every new entry
+        ReplicateLog {
+            key: String,
+            offset: usize,
+            msg: usize,
+        },
+        ReplicateLogOk,
+        Txn {
+            txn: Vec<TxnOperation>,
+        },
+        TxnOk {
+            txn: Vec<TxnOperation>,
+        },
+        ReplicateTxn {
+            writes: Vec<VersionedWrite>,
+        },
+        ReplicateTxnOk,
+        // raft, between the nodes serving lin-kv
+        RequestVote {
+            term: usize,
+            candidate_id: String,
+            last_log_index: usize,
+            last_log_term: usize,
+        },
+        RequestVoteOk {
+            term: usize,
+            vote_granted: bool,
+        },
+        AppendEntries {
+            term: usize,
+            leader_id: String,
+            prev_log_index: usize,
+            prev_log_term: usize,
+            entries: Vec<LogEntry>,
+            leader_commit: usize,
+        },
+        AppendEntriesOk {
+            term: usize,
+            success: bool,
+            // how far the log of the follower matches the leader's on success, and where
+            // the leader should go back to otherwise
+            last_log_index: usize,
+        },
+        // the leader's store as of `last_included_index`, for a follower that's missing
+        // entries the leader already compacted away
+        InstallSnapshot {
+            term: usize,
+            leader_id: String,
+            last_included_index: usize,
+            last_included_term: usize,
+            data: HashMap<String, Value>,
+        },
+        InstallSnapshotOk {
+            term: usize,
+        },
+        Error {
+            code: u32,
+            #[serde(default)]
+            text: String,
+        },
+    }
+}
+
+// What goes in the type field for `variant`, which serde's snake_case renaming
+// makes out of its name
+fn type_of(variant: &str) -> String {
+    let mut kind = String::new();
+    for (i, c) in variant.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            kind.push('_');
+        }
+        kind.push(c.to_ascii_lowercase());
+    }
+    kind
+}
+
+// A body of a type SpecificBodyFields doesn't know about, kept as its JSON fields
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Custom {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl Custom {
+    // Whether it's of a type SpecificBodyFields knows, which means it didn't parse
+    // as one because of missing or bad fields
+    pub fn is_malformed(&self) -> bool {
+        VARIANTS.iter().any(|variant| type_of(variant) == self.kind)
+    }
+}
+
+// Bodies a workload defines for itself rather than adding them to
+// SpecificBodyFields. They're sent as a SpecificBodyFields::Custom and parsed
+// back on the other side:
+//
+//     #[derive(Serialize, Deserialize)]
+//     struct Gossip { values: Vec<usize> }
+//     impl CustomBody for Gossip { const TYPE: &'static str = "gossip"; }
+//
+//     node.rpc(peer, Gossip { values }.into_body(), ...);
+//     if let Some(gossip) = Gossip::parse(&body) { ... }
+pub trait CustomBody: Serialize + DeserializeOwned {
+    // what goes in the type field, snake_case like the rest of the protocol
+    const TYPE: &'static str;
+
+    fn into_body(self) -> SpecificBodyFields {
+        // the fields are flattened in the body, so it has to be a struct with braces,
+        // `struct Ping {}` rather than `struct Ping;`
+        let fields = match serde_json::to_value(self) {
+            Ok(Value::Object(fields)) => fields,
+            other => panic!(
+                "{} doesn't serialize to a JSON object: {other:?}",
+                Self::TYPE
+            ),
+        };
+        SpecificBodyFields::Custom(Custom {
+            kind: Self::TYPE.to_string(),
+            fields,
+        })
+    }
+
+    // None if `body` is of another type, an error if it's ours but doesn't parse
+    fn parse(body: &SpecificBodyFields) -> Option<serde_json::Result<Self>> {
+        match body {
+            SpecificBodyFields::Custom(custom) if custom.kind == Self::TYPE => {
+                Some(serde_json::from_value(Value::Object(custom.fields.clone())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl SpecificBodyFields {
+    pub fn type_name(&self) -> String {
+        match self {
+            SpecificBodyFields::Custom(custom) => custom.kind.to_uppercase(),
+            body => type_of(body.variant()).to_uppercase(),
+        }
+    }
+}
+
+// Who sent a line that isn't a message we understand, and the msg_id to answer,
+// as far as that can be made out of it. Replies give None since they're never
+// answered, errors included, or two nodes could end up trading errors forever.
+pub fn sender(line: &str) -> Option<(String, Option<usize>)> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    if value.pointer("/body/in_reply_to").is_some() {
+        return None;
+    }
+    let src = value.get("src")?.as_str()?.to_string();
+    let msg_id = value
+        .pointer("/body/msg_id")
+        .and_then(Value::as_u64)
+        .map(|msg_id| msg_id as usize);
+    Some((src, msg_id))
+}
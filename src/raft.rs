@@ -0,0 +1,769 @@
+// Raft, to serve the lin-kv workload ourselves
+// https://raft.github.io/raft.pdf
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/06-raft/index.md
+//
+// Every read, write and cas is appended to the log of the leader, and only applied
+// to the key/value store once a majority of the nodes have it, reads included so
+// that a leader that lost its majority without knowing it can't serve stale
+// values. Followers forward client requests to the leader they know of and relay
+// its answer.
+//
+// A single recurring tick drives everything: the leader sends every follower what
+// it's missing, or an empty heartbeat, and everyone else starts an election if they
+// haven't heard from a leader before their randomized election deadline.
+//
+// Once enough entries have been applied, the store is kept aside as a snapshot and
+// the log up to there is dropped. A follower that needs entries the leader no
+// longer has is sent that snapshot instead.
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{
+    error::NodeError,
+    handler::{Handler, Unhandled},
+    node::{Node, RpcError},
+    protocol::{Body, LogEntry, Message, SpecificBodyFields},
+    rng::Rng,
+    wal::Record,
+};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+// Followers wait between one and two of those without news of a leader before
+// starting an election
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+// Most entries sent to a follower in a single append_entries
+const MAX_ENTRIES_PER_APPEND: usize = 100;
+// Applied entries kept in the log before it's compacted into a snapshot
+const SNAPSHOT_THRESHOLD: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Role {
+    #[default]
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Default)]
+pub struct Raft {
+    role: Role,
+    current_term: usize,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    // log[0] is the last entry the snapshot covers, index `log_start` in the
+    // paper's terms, with the request dropped. Before any snapshot it's an empty
+    // entry at index 0.
+    log: Vec<LogEntry>,
+    log_start: usize,
+    // the store as of `log_start`
+    snapshot: HashMap<String, Value>,
+    commit_index: usize,
+    last_applied: usize,
+    election_deadline: Option<Instant>,
+    votes: HashSet<String>,
+    // leader only, for every follower
+    next_index: HashMap<String, usize>,
+    match_index: HashMap<String, usize>,
+    // the state machine, keys are compared through their JSON
+    store: HashMap<String, Value>,
+    // seeded from the node id so that simulations replay the same elections
+    rng: Option<Rng>,
+}
+
+impl Raft {
+    fn last_log_index(&self) -> usize {
+        self.log_start + self.log.len() - 1
+    }
+
+    fn entry(&self, index: usize) -> Option<&LogEntry> {
+        self.log.get(index.checked_sub(self.log_start)?)
+    }
+
+    fn last_log_term(&self) -> usize {
+        self.log.last().map_or(0, |entry| entry.term)
+    }
+
+    // Before anything happened the log is only the empty entry at index 0
+    fn start_log(&mut self) {
+        if self.log.is_empty() {
+            self.log.push(LogEntry {
+                term: 0,
+                request: None,
+            });
+        }
+    }
+
+    pub(crate) fn restore_state(&mut self, term: usize, voted_for: Option<String>) {
+        self.current_term = term;
+        self.voted_for = voted_for;
+    }
+
+    pub(crate) fn restore_entries(&mut self, index: usize, entries: Vec<LogEntry>) {
+        self.start_log();
+        // what a later snapshot covers is gone already
+        let skipped = (self.log_start + 1).saturating_sub(index);
+        let index = index.max(self.log_start + 1);
+        self.log.truncate(index - self.log_start);
+        self.log.extend(entries.into_iter().skip(skipped));
+    }
+
+    // Replace everything up to `last_included_index` with `data`. Entries past the
+    // snapshot can stay if we agree on the one it ends with.
+    pub(crate) fn install(
+        &mut self,
+        last_included_index: usize,
+        last_included_term: usize,
+        data: HashMap<String, Value>,
+    ) {
+        if self
+            .entry(last_included_index)
+            .is_some_and(|entry| entry.term == last_included_term)
+        {
+            self.log.drain(..last_included_index - self.log_start);
+            self.log[0].request = None;
+        } else {
+            self.log = vec![LogEntry {
+                term: last_included_term,
+                request: None,
+            }];
+        }
+        self.log_start = last_included_index;
+        self.commit_index = self.commit_index.max(last_included_index);
+        self.last_applied = last_included_index;
+        self.store = data.clone();
+        self.snapshot = data;
+    }
+
+    // Keep the store as it is now and forget the log up to what's been applied
+    fn compact(&mut self) {
+        let applied = self.last_applied - self.log_start;
+        self.log.drain(..applied);
+        self.log[0].request = None;
+        self.log_start = self.last_applied;
+        self.snapshot = self.store.clone();
+        eprintln!("COMPACTED LOG UP TO {}", self.log_start);
+    }
+
+    // Run a committed request against the store, returning what to reply
+    fn apply(&mut self, request: SpecificBodyFields) -> Result<SpecificBodyFields, NodeError> {
+        match request {
+            SpecificBodyFields::Read { key: Some(key) } => match self.store.get(&key.to_string()) {
+                Some(value) => Ok(SpecificBodyFields::ReadOk {
+                    messages: None,
+                    value: Some(value.clone()),
+                }),
+                None => Err(key_does_not_exist(&key)),
+            },
+            SpecificBodyFields::Write { key, value } => {
+                self.store.insert(key.to_string(), value);
+                Ok(SpecificBodyFields::WriteOk)
+            }
+            SpecificBodyFields::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.store.get(&key.to_string()) {
+                Some(current) if *current == from => {
+                    self.store.insert(key.to_string(), to);
+                    Ok(SpecificBodyFields::CasOk)
+                }
+                Some(current) => Err(NodeError::PreconditionFailed(format!(
+                    "expected {from}, found {current}"
+                ))),
+                None if create_if_not_exists => {
+                    self.store.insert(key.to_string(), to);
+                    Ok(SpecificBodyFields::CasOk)
+                }
+                None => Err(key_does_not_exist(&key)),
+            },
+            other => Err(NodeError::NotSupported(format!(
+                "{} isn't a lin-kv operation",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+fn key_does_not_exist(key: &Value) -> NodeError {
+    NodeError::KeyDoesNotExist(format!("key {key} does not exist"))
+}
+
+pub struct LinKvHandler;
+
+impl Handler for LinKvHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|raft: &mut Raft, node| {
+            match body {
+                request @ (SpecificBodyFields::Read { key: Some(_) }
+                | SpecificBodyFields::Write { .. }
+                | SpecificBodyFields::Cas { .. }) => {
+                    raft.client_request(node, src, msg_id, request)?
+                }
+                SpecificBodyFields::RequestVote {
+                    term,
+                    candidate_id,
+                    last_log_index,
+                    last_log_term,
+                } => raft.request_vote(
+                    node,
+                    src,
+                    msg_id,
+                    term,
+                    candidate_id,
+                    last_log_index,
+                    last_log_term,
+                ),
+                SpecificBodyFields::AppendEntries {
+                    term,
+                    leader_id,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                } => raft.append_entries(
+                    node,
+                    src,
+                    msg_id,
+                    term,
+                    leader_id,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                ),
+                SpecificBodyFields::InstallSnapshot {
+                    term,
+                    leader_id,
+                    last_included_index,
+                    last_included_term,
+                    data,
+                } => raft.install_snapshot(
+                    node,
+                    src,
+                    msg_id,
+                    term,
+                    leader_id,
+                    last_included_index,
+                    last_included_term,
+                    data,
+                ),
+                other => return Err(Unhandled::Passed(other)),
+            }
+            Ok(())
+        })
+    }
+
+    fn init(&self, node: &mut Node) {
+        node.with_state(Raft::start);
+    }
+}
+
+fn peers(node: &Node) -> Vec<String> {
+    node.node_ids
+        .iter()
+        .filter(|peer| **peer != node.id)
+        .cloned()
+        .collect()
+}
+
+fn majority(node: &Node) -> usize {
+    node.node_ids.len() / 2 + 1
+}
+
+impl Raft {
+    fn start(&mut self, node: &mut Node) {
+        let mut hasher = DefaultHasher::new();
+        node.id.hash(&mut hasher);
+        self.rng = Some(Rng::new(hasher.finish()));
+        // or where the log left off before a restart
+        self.start_log();
+        self.reset_election_deadline(node);
+        node.schedule_every(TICK_INTERVAL, |node| node.with_state(Raft::tick));
+    }
+
+    fn tick(&mut self, node: &mut Node) {
+        match self.role {
+            Role::Leader => self.replicate(node),
+            Role::Follower | Role::Candidate => {
+                if self
+                    .election_deadline
+                    .is_some_and(|deadline| node.now >= deadline)
+                {
+                    self.elect(node);
+                }
+            }
+        }
+    }
+
+    fn reset_election_deadline(&mut self, node: &Node) {
+        let jitter = self.rng.as_mut().map_or(0.0, Rng::next_f64);
+        self.election_deadline = Some(node.now + ELECTION_TIMEOUT.mul_f64(1.0 + jitter));
+    }
+
+    // Anything from a later term means we're behind, whatever we thought we were
+    fn observe_term(&mut self, node: &mut Node, term: usize) {
+        if term > self.current_term {
+            if self.role != Role::Follower {
+                eprintln!("STEPPING DOWN, TERM {term} STARTED");
+            }
+            self.current_term = term;
+            self.voted_for = None;
+            self.role = Role::Follower;
+            self.leader = None;
+            self.persist_state(node);
+        }
+    }
+
+    // Our term and vote, which must survive a restart for a vote to only ever be
+    // given once in a term
+    fn persist_state(&self, node: &mut Node) {
+        node.persist(Record::RaftState {
+            term: self.current_term,
+            voted_for: self.voted_for.clone(),
+        });
+    }
+
+    fn persist_snapshot(&self, node: &mut Node) {
+        if node.persisting() {
+            node.persist(Record::RaftSnapshot {
+                index: self.log_start,
+                term: self.log[0].term,
+                data: self.snapshot.clone(),
+            });
+        }
+    }
+
+    fn elect(&mut self, node: &mut Node) {
+        self.current_term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(node.id.clone());
+        self.persist_state(node);
+        self.votes = HashSet::from([node.id.clone()]);
+        self.leader = None;
+        self.reset_election_deadline(node);
+        let term = self.current_term;
+        eprintln!("STARTING ELECTION FOR TERM {term}");
+        if self.votes.len() >= majority(node) {
+            self.become_leader(node);
+            return;
+        }
+
+        for peer in peers(node) {
+            let body = SpecificBodyFields::RequestVote {
+                term,
+                candidate_id: node.id.clone(),
+                last_log_index: self.last_log_index(),
+                last_log_term: self.last_log_term(),
+            };
+            node.rpc_with_timeout(peer.clone(), body, ELECTION_TIMEOUT, move |node, reply| {
+                let Ok(Message {
+                    body:
+                        Body {
+                            specific_fields:
+                                SpecificBodyFields::RequestVoteOk {
+                                    term: their_term,
+                                    vote_granted,
+                                },
+                            ..
+                        },
+                    ..
+                }) = reply
+                else {
+                    return;
+                };
+                node.with_state(|raft: &mut Raft, node| {
+                    raft.observe_term(node, their_term);
+                    if raft.role == Role::Candidate && raft.current_term == term && vote_granted {
+                        raft.votes.insert(peer);
+                        if raft.votes.len() >= majority(node) {
+                            raft.become_leader(node);
+                        }
+                    }
+                })
+            });
+        }
+    }
+
+    fn become_leader(&mut self, node: &mut Node) {
+        eprintln!("BECAME LEADER FOR TERM {}", self.current_term);
+        self.role = Role::Leader;
+        self.leader = Some(node.id.clone());
+        let next = self.last_log_index() + 1;
+        for peer in peers(node) {
+            self.next_index.insert(peer.clone(), next);
+            self.match_index.insert(peer, 0);
+        }
+        // Entries of earlier terms we have but don't know to be committed only get
+        // committed along with one of ours, see 5.4.2, so we make one right away
+        // rather than leave them hanging until a client sends something
+        let noop = LogEntry {
+            term: self.current_term,
+            request: None,
+        };
+        self.append(node, noop);
+    }
+
+    // Append an entry of our term as the leader, and send it to the followers
+    fn append(&mut self, node: &mut Node, entry: LogEntry) {
+        node.persist(Record::RaftEntries {
+            index: self.last_log_index() + 1,
+            entries: vec![entry.clone()],
+        });
+        self.log.push(entry);
+        // alone, we're our own majority
+        self.advance_commit(node);
+        self.replicate(node);
+    }
+
+    // Send every follower the entries it's missing, which is an empty heartbeat for
+    // those that are up to date
+    fn replicate(&mut self, node: &mut Node) {
+        let term = self.current_term;
+        for peer in peers(node) {
+            let next = self.next_index.get(&peer).copied().unwrap_or(1);
+            let prev_log_index = next - 1;
+            if prev_log_index < self.log_start {
+                self.send_snapshot(node, peer);
+                continue;
+            }
+            let entries: Vec<LogEntry> = self.log[next - self.log_start..]
+                .iter()
+                .take(MAX_ENTRIES_PER_APPEND)
+                .cloned()
+                .collect();
+            let sent = entries.len();
+            let body = SpecificBodyFields::AppendEntries {
+                term,
+                leader_id: node.id.clone(),
+                prev_log_index,
+                prev_log_term: self.log[prev_log_index - self.log_start].term,
+                entries,
+                leader_commit: self.commit_index,
+            };
+            node.rpc_with_timeout(peer.clone(), body, ELECTION_TIMEOUT, move |node, reply| {
+                let Ok(Message {
+                    body:
+                        Body {
+                            specific_fields:
+                                SpecificBodyFields::AppendEntriesOk {
+                                    term: their_term,
+                                    success,
+                                    last_log_index,
+                                },
+                            ..
+                        },
+                    ..
+                }) = reply
+                else {
+                    return;
+                };
+                node.with_state(|raft: &mut Raft, node| {
+                    raft.observe_term(node, their_term);
+                    // a reply to a leader we no longer are
+                    if raft.role != Role::Leader || raft.current_term != term {
+                        return;
+                    }
+                    if success {
+                        let matched = raft.match_index.entry(peer.clone()).or_default();
+                        *matched = (*matched).max(prev_log_index + sent);
+                        let matched = *matched;
+                        raft.next_index.insert(peer, matched + 1);
+                        raft.advance_commit(node);
+                    } else {
+                        let next = raft.next_index.entry(peer).or_insert(1);
+                        *next = (*next - 1).min(last_log_index + 1).max(1);
+                    }
+                })
+            });
+        }
+    }
+
+    fn send_snapshot(&mut self, node: &mut Node, peer: String) {
+        let term = self.current_term;
+        let last_included_index = self.log_start;
+        let body = SpecificBodyFields::InstallSnapshot {
+            term,
+            leader_id: node.id.clone(),
+            last_included_index,
+            last_included_term: self.log[0].term,
+            data: self.snapshot.clone(),
+        };
+        node.rpc_with_timeout(peer.clone(), body, ELECTION_TIMEOUT, move |node, reply| {
+            let Ok(Message {
+                body:
+                    Body {
+                        specific_fields: SpecificBodyFields::InstallSnapshotOk { term: their_term },
+                        ..
+                    },
+                ..
+            }) = reply
+            else {
+                return;
+            };
+            node.with_state(|raft: &mut Raft, node| {
+                raft.observe_term(node, their_term);
+                if raft.role != Role::Leader || raft.current_term != term {
+                    return;
+                }
+                let matched = raft.match_index.entry(peer.clone()).or_default();
+                *matched = (*matched).max(last_included_index);
+                let matched = *matched;
+                raft.next_index.insert(peer, matched + 1);
+                raft.advance_commit(node);
+            })
+        });
+    }
+
+    // Commit the latest entry of our term that a majority has, and everything before
+    // it. Entries of earlier terms are never committed by counting, see 5.4.2.
+    fn advance_commit(&mut self, node: &mut Node) {
+        let majority = majority(node);
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.log[index - self.log_start].term != self.current_term {
+                break;
+            }
+            let replicas = 1 + self
+                .match_index
+                .values()
+                .filter(|matched| **matched >= index)
+                .count();
+            if replicas >= majority {
+                self.commit_index = index;
+                break;
+            }
+        }
+        self.apply_committed(node);
+    }
+
+    fn apply_committed(&mut self, node: &mut Node) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let index = self.last_applied - self.log_start;
+            let Some(request) = self.log[index].request.clone() else {
+                continue;
+            };
+            let answer = self
+                .apply(request.body.specific_fields)
+                .unwrap_or_else(SpecificBodyFields::from);
+            // whoever the client sent it to forwarded it to the leader, and is waiting
+            // for the leader's answer
+            if self.role == Role::Leader {
+                node.reply(request.src, request.body.msg_id, answer);
+            }
+        }
+        if self.last_applied - self.log_start >= SNAPSHOT_THRESHOLD {
+            self.compact();
+            self.persist_snapshot(node);
+        }
+    }
+
+    fn client_request(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        request: SpecificBodyFields,
+    ) -> Result<(), NodeError> {
+        if self.role == Role::Leader {
+            let entry = LogEntry {
+                term: self.current_term,
+                request: Some(Message {
+                    src,
+                    dest: node.id.clone(),
+                    body: Body {
+                        specific_fields: request,
+                        msg_id,
+                        in_reply_to: None,
+                    },
+                }),
+            };
+            self.append(node, entry);
+            return Ok(());
+        }
+
+        // a node forwarding to us thought we were the leader, don't bounce it around
+        if node.node_ids.contains(&src) {
+            return Err(NodeError::TemporarilyUnavailable(String::from(
+                "not the leader anymore",
+            )));
+        }
+        let Some(leader) = self.leader.clone() else {
+            return Err(NodeError::TemporarilyUnavailable(String::from(
+                "no leader at the moment",
+            )));
+        };
+        node.rpc(leader, request, move |node, reply| {
+            let answer = match reply {
+                Ok(reply) => reply.body.specific_fields,
+                // the leader may or may not have applied it, so we can't say
+                Err(RpcError::Timeout) => {
+                    NodeError::Timeout(String::from("timed out waiting for the leader")).into()
+                }
+            };
+            node.reply(src, msg_id, answer);
+        });
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn request_vote(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        term: usize,
+        candidate_id: String,
+        last_log_index: usize,
+        last_log_term: usize,
+    ) {
+        self.observe_term(node, term);
+        let up_to_date =
+            (last_log_term, last_log_index) >= (self.last_log_term(), self.last_log_index());
+        let vote_granted = term == self.current_term
+            && up_to_date
+            && self
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted| *voted == candidate_id);
+        if vote_granted {
+            self.voted_for = Some(candidate_id);
+            self.persist_state(node);
+            self.reset_election_deadline(node);
+        }
+        let answer = SpecificBodyFields::RequestVoteOk {
+            term: self.current_term,
+            vote_granted,
+        };
+        node.reply(src, msg_id, answer);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append_entries(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        term: usize,
+        leader_id: String,
+        prev_log_index: usize,
+        prev_log_term: usize,
+        entries: Vec<LogEntry>,
+        leader_commit: usize,
+    ) {
+        self.observe_term(node, term);
+        let current_term = self.current_term;
+        if term < current_term {
+            let answer = SpecificBodyFields::AppendEntriesOk {
+                term: current_term,
+                success: false,
+                last_log_index: self.last_log_index(),
+            };
+            node.reply(src, msg_id, answer);
+            return;
+        }
+        // there's a leader for our term, and it isn't us
+        self.role = Role::Follower;
+        self.leader = Some(leader_id);
+        self.reset_election_deadline(node);
+
+        // what comes before our snapshot is committed so it can only match, skip it
+        let (prev_log_index, prev_log_term, entries) = if prev_log_index < self.log_start {
+            let skipped = self.log_start - prev_log_index;
+            let entries = entries.into_iter().skip(skipped).collect();
+            (self.log_start, self.log[0].term, entries)
+        } else {
+            (prev_log_index, prev_log_term, entries)
+        };
+        if self
+            .entry(prev_log_index)
+            .is_none_or(|entry| entry.term != prev_log_term)
+        {
+            let last_log_index = self.last_log_index().min(prev_log_index.saturating_sub(1));
+            let answer = SpecificBodyFields::AppendEntriesOk {
+                term: current_term,
+                success: false,
+                last_log_index,
+            };
+            node.reply(src, msg_id, answer);
+            return;
+        }
+
+        let received = entries.len();
+        let mut changed_from = None;
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            match self.entry(index) {
+                Some(existing) if existing.term == entry.term => continue,
+                // conflicts with the leader, which always wins
+                Some(_) => self.log.truncate(index - self.log_start),
+                None => {}
+            }
+            changed_from.get_or_insert(index);
+            self.log.push(entry);
+        }
+        if let Some(index) = changed_from
+            && node.persisting()
+        {
+            let entries = self.log[index - self.log_start..].to_vec();
+            node.persist(Record::RaftEntries { index, entries });
+        }
+        let last_new_index = prev_log_index + received;
+        // only what we know matches the leader's log can be committed
+        let commit_index = leader_commit.min(last_new_index);
+        if commit_index > self.commit_index {
+            self.commit_index = commit_index;
+            self.apply_committed(node);
+        }
+        let answer = SpecificBodyFields::AppendEntriesOk {
+            term: current_term,
+            success: true,
+            last_log_index: last_new_index,
+        };
+        node.reply(src, msg_id, answer);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn install_snapshot(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        term: usize,
+        leader_id: String,
+        last_included_index: usize,
+        last_included_term: usize,
+        data: HashMap<String, Value>,
+    ) {
+        self.observe_term(node, term);
+        let current_term = self.current_term;
+        let answer = SpecificBodyFields::InstallSnapshotOk { term: current_term };
+        if term < current_term {
+            node.reply(src, msg_id, answer);
+            return;
+        }
+        self.role = Role::Follower;
+        self.leader = Some(leader_id);
+        self.reset_election_deadline(node);
+        // a late or duplicated snapshot, we've applied all of it already
+        if last_included_index <= self.last_applied {
+            node.reply(src, msg_id, answer);
+            return;
+        }
+
+        eprintln!("INSTALLING SNAPSHOT UP TO {last_included_index}");
+        self.install(last_included_index, last_included_term, data);
+        self.persist_snapshot(node);
+        node.reply(src, msg_id, answer);
+    }
+}
@@ -0,0 +1,201 @@
+// Drives a node over a stream of JSON lines, the way maelstrom talks to it on stdio
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufWriter, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Instant,
+};
+
+use serde_json::json;
+
+use crate::{
+    error::NodeError,
+    node::{Node, Output, TimerId},
+    protocol::{self, Message},
+};
+
+// Generic over any BufRead, a node talking to its peers over TCP goes through tcp.rs.
+// Input is read on its own thread and fed through a channel so that the node is
+// only ever touched here, either by a message or by a due timer.
+pub fn run<R, W>(mut node: Node, input: R, mut output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input.lines() {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut deadlines: BTreeSet<(Instant, TimerId)> = BTreeSet::new();
+    loop {
+        while let Some(&(deadline, id)) = deadlines.first()
+            && deadline <= Instant::now()
+        {
+            deadlines.pop_first();
+            let outputs = node.fire(id, Instant::now());
+            dispatch(outputs, &mut output, &mut deadlines)?;
+        }
+
+        let received = match deadlines.first() {
+            Some((deadline, _)) => {
+                rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => rx.recv().map_err(RecvTimeoutError::from),
+        };
+        let line = match received {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break, // EOF
+        };
+
+        // eprintln!("RAW RECEIVED: {}", line);
+        let outputs = handle_line(&mut node, &line);
+        dispatch(outputs, &mut output, &mut deadlines)?;
+    }
+    Ok(())
+}
+
+// Hand a line of input to the node, answering requests that don't parse
+pub(crate) fn handle_line(node: &mut Node, line: &str) -> Vec<Output> {
+    match serde_json::from_str::<Message>(line.trim_end()) {
+        Ok(msg) => node.handle(msg, Instant::now()),
+        Err(e) => {
+            eprintln!("Error deserializing input: {e}");
+            match protocol::sender(line) {
+                Some((src, msg_id)) => {
+                    let error = NodeError::MalformedRequest(e.to_string());
+                    node.refuse(src, msg_id, error, Instant::now())
+                }
+                None => Vec::new(),
+            }
+        }
+    }
+}
+
+fn dispatch<W: Write>(
+    outputs: Vec<Output>,
+    output: &mut W,
+    deadlines: &mut BTreeSet<(Instant, TimerId)>,
+) -> io::Result<()> {
+    for out in outputs {
+        match out {
+            Output::Send(message) => writeln!(output, "{:}", json!(message))?,
+            Output::Timer { id, deadline } => {
+                deadlines.insert((deadline, id));
+            }
+        }
+    }
+    output.flush()
+}
+
+// What the worker of the threaded runtime waits on
+pub(crate) enum Event {
+    Line(io::Result<String>),
+    Timer(TimerId),
+    // the input ran out
+    End,
+}
+
+// Same as `run`, but nothing but the node runs on the calling thread. Input is
+// read on one thread, timers are kept on another which sends them back once due,
+// both through the channel the node is fed from, and messages are serialized and
+// written out on a third, so a slow or blocked output never holds the node up.
+pub fn run_threaded<R, W>(mut node: Node, input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let (events, rx) = mpsc::channel();
+
+    let lines = events.clone();
+    thread::spawn(move || {
+        for line in input.lines() {
+            if lines.send(Event::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = lines.send(Event::End);
+    });
+
+    let (timers, timers_rx) = mpsc::channel();
+    thread::spawn(move || keep_timers(timers_rx, events));
+
+    let (messages, messages_rx) = mpsc::channel();
+    let writer = thread::spawn(move || write_messages(messages_rx, output));
+
+    let mut result = Ok(());
+    'events: while let Ok(event) = rx.recv() {
+        let outputs = match event {
+            Event::Line(Ok(line)) => handle_line(&mut node, &line),
+            Event::Line(Err(e)) => {
+                result = Err(e);
+                break;
+            }
+            Event::Timer(id) => node.fire(id, Instant::now()),
+            Event::End => break,
+        };
+        for out in outputs {
+            let sent = match out {
+                Output::Send(message) => messages.send(message).is_ok(),
+                Output::Timer { id, deadline } => timers.send((deadline, id)).is_ok(),
+            };
+            // the writer only hangs up on an error, which joining it returns
+            if !sent {
+                break 'events;
+            }
+        }
+    }
+    // whatever is still queued gets written before we return
+    drop(messages);
+    let written = writer.join().expect("writer thread panicked");
+    result.and(written)
+}
+
+// Wait for the earliest timer while taking in new ones, and send every timer
+// back to the worker once it's due
+pub(crate) fn keep_timers(timers: Receiver<(Instant, TimerId)>, events: Sender<Event>) {
+    let mut deadlines: BTreeSet<(Instant, TimerId)> = BTreeSet::new();
+    loop {
+        while let Some(&(deadline, id)) = deadlines.first()
+            && deadline <= Instant::now()
+        {
+            deadlines.pop_first();
+            if events.send(Event::Timer(id)).is_err() {
+                return;
+            }
+        }
+
+        let received = match deadlines.first() {
+            Some((deadline, _)) => {
+                timers.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => timers.recv().map_err(RecvTimeoutError::from),
+        };
+        match received {
+            Ok(timer) => {
+                deadlines.insert(timer);
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+// Write out whatever was sent, flushing once there's nothing left waiting
+fn write_messages<W: Write>(messages: Receiver<Message>, output: W) -> io::Result<()> {
+    let mut output = BufWriter::new(output);
+    while let Ok(message) = messages.recv() {
+        writeln!(output, "{:}", json!(message))?;
+        for message in messages.try_iter() {
+            writeln!(output, "{:}", json!(message))?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,458 @@
+// Runs a whole cluster in a single process, without maelstrom. Nodes are driven
+// through their sans-IO interface, every message they send is put on an in-memory
+// network and delivered to whoever its dest is, and timers fire on a virtual clock
+// that jumps straight to the next event, so a few seconds of gossip take a few
+// milliseconds to simulate.
+//
+// Everything random about the network, latencies, drops, duplicates and which nodes
+// end up on which side of a partition, comes out of a single seeded generator and
+// events happening at the same instant are ordered by when they were scheduled, so
+// a run is entirely decided by its seed and a failing seed replays exactly.
+//
+// The kv services are played by the simulation itself, behind the same network as
+// the nodes, so requests to them can be lost and partitions can cut a node off
+// from them like in maelstrom. Messages to anyone else, the clients, are only
+// recorded in the log, which is where replies to the requests injected with
+// `client` end up.
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    kv::{KvService, Store},
+    node::{Node, Output, TimerId},
+    protocol::{Body, Message, SpecificBodyFields},
+    rng::Rng,
+    workload::Workload,
+};
+
+// Client the simulation sends init and topology from
+const CONTROL_CLIENT: &str = "c0";
+
+// How long a message takes to get to its dest, the same distributions maelstrom
+// offers through --latency and --latency-dist
+#[derive(Debug, Clone, Copy)]
+pub enum Latency {
+    Constant(Duration),
+    Uniform { min: Duration, max: Duration },
+    Exponential { mean: Duration },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Constant(Duration::ZERO)
+    }
+}
+
+impl Latency {
+    pub fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Latency::Constant(latency) => latency,
+            Latency::Uniform { min, max } => {
+                min + (max.saturating_sub(min)).mul_f64(rng.next_f64())
+            }
+            Latency::Exponential { mean } => mean.mul_f64(-(1.0 - rng.next_f64()).ln()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimConfig {
+    pub seed: u64,
+    // latency of every message unless its link has its own
+    pub latency: Latency,
+    // (src, dest) -> latency
+    pub links: HashMap<(String, String), Latency>,
+    // probability for a message between two nodes, or a node and a kv service, to
+    // be lost or delivered twice. Messages from and to clients are never lost nor
+    // duplicated, as in maelstrom.
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+}
+
+// Nodes in different groups can't talk to each other between `from` and `until`,
+// nodes that aren't in any group can still talk to everyone. Groups can hold kv
+// services too.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub from: Duration,
+    pub until: Duration,
+    pub groups: Vec<Vec<String>>,
+}
+
+impl Partition {
+    fn cuts(&self, at: Duration, a: &str, b: &str) -> bool {
+        let group = |node: &str| self.groups.iter().position(|g| g.iter().any(|n| n == node));
+        self.from <= at
+            && at < self.until
+            && matches!((group(a), group(b)), (Some(a), Some(b)) if a != b)
+    }
+}
+
+// A message that went over the network, along with when it did
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    // since the start of the simulation
+    pub sent_at: Duration,
+    pub at: Duration,
+    pub message: Message,
+}
+
+#[derive(Debug)]
+enum Event {
+    Deliver { sent_at: Duration, message: Message },
+    Fire { node: String, timer: TimerId },
+}
+
+pub struct Sim {
+    config: SimConfig,
+    rng: Rng,
+    start: Instant,
+    now: Instant,
+    nodes: BTreeMap<String, Node>,
+    // service -> what it holds, created on the first request to it
+    kv: BTreeMap<String, Store>,
+    // keyed by when they happen, then by when they were scheduled
+    events: BTreeMap<(Instant, u64), Event>,
+    event_counter: u64,
+    // messages on the network right now
+    in_flight: usize,
+    partitions: Vec<Partition>,
+    log: Vec<Delivery>,
+    dropped: Vec<Message>,
+    client_msg_counter: usize,
+}
+
+// Every node is a neighbour of every other one
+pub fn full_topology(node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    node_ids
+        .iter()
+        .map(|id| {
+            let neighbours = node_ids.iter().filter(|n| *n != id).cloned().collect();
+            (id.clone(), neighbours)
+        })
+        .collect()
+}
+
+// Nodes are chained one after the other, the worst case for propagation
+pub fn line_topology(node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let neighbours = [i.checked_sub(1), Some(i + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|j| node_ids.get(j).cloned())
+                .collect();
+            (id.clone(), neighbours)
+        })
+        .collect()
+}
+
+// Nodes laid out on a square grid, each talking to the ones next to it, which is
+// what maelstrom sends by default
+pub fn grid_topology(node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    let width = (1..).find(|w| w * w >= node_ids.len()).unwrap_or(1);
+    node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let left = (i % width != 0).then(|| i - 1);
+            let right = ((i + 1) % width != 0).then_some(i + 1);
+            let up = i.checked_sub(width);
+            let down = Some(i + width);
+            let neighbours = [left, right, up, down]
+                .into_iter()
+                .flatten()
+                .filter_map(|j| node_ids.get(j).cloned())
+                .collect();
+            (id.clone(), neighbours)
+        })
+        .collect()
+}
+
+impl Sim {
+    // Start `node_count` nodes named n0, n1... on a perfect network and initialise them
+    pub fn new(node_count: usize, workload: Workload) -> Self {
+        Sim::with_config(node_count, workload, SimConfig::default())
+    }
+
+    pub fn with_config(node_count: usize, workload: Workload, config: SimConfig) -> Self {
+        let now = Instant::now();
+        let node_ids: Vec<String> = (0..node_count).map(|i| format!("n{i}")).collect();
+        let mut sim = Sim {
+            rng: Rng::new(config.seed),
+            config,
+            start: now,
+            now,
+            nodes: node_ids
+                .iter()
+                .map(|id| (id.clone(), Node::new(workload)))
+                .collect(),
+            kv: BTreeMap::new(),
+            events: BTreeMap::new(),
+            event_counter: 0,
+            in_flight: 0,
+            partitions: Vec::new(),
+            log: Vec::new(),
+            dropped: Vec::new(),
+            client_msg_counter: 0,
+        };
+        for id in &node_ids {
+            sim.client(
+                CONTROL_CLIENT,
+                id,
+                SpecificBodyFields::Init {
+                    node_id: id.clone(),
+                    node_ids: node_ids.clone(),
+                },
+            );
+        }
+        sim.run_until_quiet();
+        sim
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.config.seed
+    }
+
+    pub fn node_ids(&self) -> Vec<String> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    // Time elapsed since the start of the simulation
+    pub fn elapsed(&self) -> Duration {
+        self.now - self.start
+    }
+
+    // What `service` holds, if anything was ever sent to it
+    pub fn kv(&self, service: KvService) -> Option<&Store> {
+        self.kv.get(service.address())
+    }
+
+    pub fn log(&self) -> &[Delivery] {
+        &self.log
+    }
+
+    // Messages lost to drops or partitions
+    pub fn dropped(&self) -> &[Message] {
+        &self.dropped
+    }
+
+    // Send every node its neighbours
+    pub fn topology(&mut self, topology: HashMap<String, Vec<String>>) {
+        for id in self.node_ids() {
+            let body = SpecificBodyFields::Topology {
+                topology: topology.clone(),
+            };
+            self.client(CONTROL_CLIENT, &id, body);
+        }
+        self.run_until_quiet();
+    }
+
+    pub fn partition(&mut self, partition: Partition) {
+        self.partitions.push(partition);
+    }
+
+    // What maelstrom's partition nemesis does, from now on and until `until` the
+    // nodes are split in two random halves for `interval` then healed for `interval`
+    pub fn partition_periodically(&mut self, interval: Duration, until: Duration) {
+        let mut from = self.elapsed();
+        while from < until {
+            let mut nodes = self.node_ids();
+            self.rng.shuffle(&mut nodes);
+            let minority = nodes.split_off(nodes.len().div_ceil(2));
+            self.partitions.push(Partition {
+                from,
+                until: (from + interval).min(until),
+                groups: vec![nodes, minority],
+            });
+            from += interval * 2;
+        }
+    }
+
+    // Put a request from `client` on the network, returns its msg_id
+    pub fn client(&mut self, client: &str, dest: &str, body: SpecificBodyFields) -> usize {
+        let msg_id = self.client_msg_counter;
+        self.client_msg_counter += 1;
+        self.send(Message {
+            src: client.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+            },
+        });
+        msg_id
+    }
+
+    // The reply `client` got to its request `msg_id`, if it got one yet
+    pub fn reply_to(&self, client: &str, msg_id: usize) -> Option<&Message> {
+        self.log
+            .iter()
+            .map(|delivery| &delivery.message)
+            .find(|message| message.dest == client && message.body.in_reply_to == Some(msg_id))
+    }
+
+    // Send a request from `client` and run until it gets its reply, or until
+    // `timeout` went by
+    pub fn call(
+        &mut self,
+        client: &str,
+        dest: &str,
+        body: SpecificBodyFields,
+        timeout: Duration,
+    ) -> Option<Message> {
+        let msg_id = self.client(client, dest, body);
+        let until = self.now + timeout;
+        while self.reply_to(client, msg_id).is_none() && self.step_until(until) {}
+        self.reply_to(client, msg_id).cloned()
+    }
+
+    // Run the next event, whether it's a message being delivered or a timer going
+    // off. Returns false once there's nothing left to do at all.
+    pub fn step(&mut self) -> bool {
+        let Some(((at, _), event)) = self.events.pop_first() else {
+            return false;
+        };
+        self.now = self.now.max(at);
+        match event {
+            Event::Deliver { sent_at, message } => self.deliver(sent_at, message),
+            Event::Fire { node, timer } => {
+                if let Some(n) = self.nodes.get_mut(&node) {
+                    let outputs = n.fire(timer, self.now);
+                    self.dispatch(&node, outputs);
+                }
+            }
+        }
+        true
+    }
+
+    fn step_until(&mut self, until: Instant) -> bool {
+        match self.events.first_key_value() {
+            Some(((at, _), _)) if *at <= until => self.step(),
+            _ => false,
+        }
+    }
+
+    // Run until `duration` went by on the virtual clock
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        while self.step_until(until) {}
+        self.now = self.now.max(until);
+    }
+
+    // Run until no message is on its way anywhere, timers still go off if that
+    // takes a while
+    pub fn run_until_quiet(&mut self) {
+        while self.in_flight > 0 && self.step() {}
+    }
+
+    fn schedule(&mut self, at: Instant, event: Event) {
+        self.events.insert((at, self.event_counter), event);
+        self.event_counter += 1;
+    }
+
+    fn latency(&mut self, src: &str, dest: &str) -> Duration {
+        let latency = self
+            .config
+            .links
+            .get(&(src.to_string(), dest.to_string()))
+            .copied()
+            .unwrap_or(self.config.latency);
+        latency.sample(&mut self.rng)
+    }
+
+    fn send(&mut self, message: Message) {
+        let sent_at = self.elapsed();
+        let on_network = self.on_network(&message.src) && self.on_network(&message.dest);
+        if on_network {
+            let cut = self
+                .partitions
+                .iter()
+                .any(|p| p.cuts(sent_at, &message.src, &message.dest));
+            if cut || self.rng.chance(self.config.drop_rate) {
+                self.dropped.push(message);
+                return;
+            }
+            if self.rng.chance(self.config.duplicate_rate) {
+                let latency = self.latency(&message.src, &message.dest);
+                self.in_flight += 1;
+                self.schedule(
+                    self.now + latency,
+                    Event::Deliver {
+                        sent_at,
+                        message: message.clone(),
+                    },
+                );
+            }
+        }
+        let latency = self.latency(&message.src, &message.dest);
+        self.in_flight += 1;
+        self.schedule(self.now + latency, Event::Deliver { sent_at, message });
+    }
+
+    fn deliver(&mut self, sent_at: Duration, message: Message) {
+        self.in_flight -= 1;
+        self.log.push(Delivery {
+            sent_at,
+            at: self.elapsed(),
+            message: message.clone(),
+        });
+        let dest = message.dest.clone();
+        if let Some(node) = self.nodes.get_mut(&dest) {
+            let outputs = node.handle(message, self.now);
+            self.dispatch(&dest, outputs);
+        } else if is_kv(&dest) {
+            let body = self
+                .kv
+                .entry(dest.clone())
+                .or_default()
+                .serve(message.body.specific_fields);
+            let msg_id = self.client_msg_counter;
+            self.client_msg_counter += 1;
+            self.send(Message {
+                src: dest,
+                dest: message.src,
+                body: Body {
+                    specific_fields: body,
+                    msg_id: Some(msg_id),
+                    in_reply_to: message.body.msg_id,
+                },
+            });
+        }
+    }
+
+    // Whether the network can lose messages to or from `id`, anyone but the clients
+    fn on_network(&self, id: &str) -> bool {
+        self.nodes.contains_key(id) || is_kv(id)
+    }
+
+    fn dispatch(&mut self, node: &str, outputs: Vec<Output>) {
+        for out in outputs {
+            match out {
+                Output::Send(message) => self.send(message),
+                Output::Timer { id, deadline } => self.schedule(
+                    deadline,
+                    Event::Fire {
+                        node: node.to_string(),
+                        timer: id,
+                    },
+                ),
+            }
+        }
+    }
+}
+
+fn is_kv(id: &str) -> bool {
+    [KvService::Seq, KvService::Lin, KvService::Lww]
+        .iter()
+        .any(|service| service.address() == id)
+}
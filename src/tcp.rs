@@ -0,0 +1,287 @@
+// Runs a node outside maelstrom, talking to its peers and clients over TCP with
+// the same newline-delimited JSON messages.
+//
+// The cluster is a static list of node ids and the address each listens on, and
+// every node gets the same one, which takes the place of maelstrom's init. A node
+// connects to a peer the first time it has something to send it, and the peer
+// answers over its own connection the other way, so a connection only ever
+// carries messages one way between nodes. Clients are anyone else: they connect to
+// any node and get their replies back on the connection they came in on.
+//
+// Like maelstrom's network, delivery is best effort. Messages to a peer that can't
+// be reached are dropped, and retrying is left to the node. Every peer is written
+// to from its own thread, so one that's down doesn't hold up the others.
+//
+// Nothing here stands in for maelstrom's kv services though, messages to them have
+// nowhere to go. Workloads relying on one, like g-counter on seq-kv, are refused
+// rather than left hanging on their first read.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    node::{Node, Output},
+    protocol::{self, Body, Message, SpecificBodyFields},
+    runtime::{self, Event},
+    workload::Workload,
+};
+
+// How long connecting to a peer may hold up everything else we send it
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+// How long messages to a peer we couldn't connect to are dropped before trying again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Where init comes from as far as the node knows, its init_ok goes nowhere
+const CLUSTER: &str = "cluster";
+
+// e.g. {"nodes": {"n0": "127.0.0.1:7000", "n1": "127.0.0.1:7001"}}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClusterConfig {
+    pub nodes: BTreeMap<String, SocketAddr>,
+}
+
+impl ClusterConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let config = fs::read_to_string(path)?;
+        serde_json::from_str(&config).map_err(io::Error::other)
+    }
+}
+
+// What the thread writing to clients is handed
+enum Outgoing {
+    Message(Message),
+    // where to send what's addressed to a client
+    Client(String, TcpStream),
+}
+
+// Whether nodes serving `workload` can run over TCP at all
+pub fn check_workload(workload: Workload) -> io::Result<()> {
+    match workload.kv_services() {
+        [] => Ok(()),
+        [service, ..] => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{workload:?} needs {}, which isn't served over TCP",
+                service.address()
+            ),
+        )),
+    }
+}
+
+// Listen on the address `id` has in `config` and serve forever
+pub fn serve(node: Node, id: &str, config: &ClusterConfig) -> io::Result<()> {
+    let Some(addr) = config.nodes.get(id) else {
+        return Err(io::Error::other(format!(
+            "{id} isn't in the cluster config"
+        )));
+    };
+    serve_on(TcpListener::bind(addr)?, node, id, config)
+}
+
+// Same as `serve` with a listener that's already bound, like one on port 0. Only
+// returns if one of the threads it relies on stopped.
+pub fn serve_on(
+    listener: TcpListener,
+    mut node: Node,
+    id: &str,
+    config: &ClusterConfig,
+) -> io::Result<()> {
+    let (events, rx) = mpsc::channel();
+    let (clients, clients_rx) = mpsc::channel();
+
+    let peers: HashSet<String> = config.nodes.keys().cloned().collect();
+    let (lines, registrations) = (events.clone(), clients.clone());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (lines, registrations) = (lines.clone(), registrations.clone());
+                    let peers = peers.clone();
+                    thread::spawn(move || read_connection(stream, peers, lines, registrations));
+                }
+                Err(e) => eprintln!("FAILED TO ACCEPT A CONNECTION: {e}"),
+            }
+        }
+    });
+
+    let (timers, timers_rx) = mpsc::channel();
+    thread::spawn(move || runtime::keep_timers(timers_rx, events));
+
+    thread::spawn(move || write_to_clients(clients_rx));
+    // every peer gets its own writer, so one that's down only holds up what's sent
+    // to it
+    let peers: HashMap<String, Sender<Message>> = config
+        .nodes
+        .iter()
+        .map(|(peer, addr)| {
+            let (messages, messages_rx) = mpsc::channel();
+            let (name, addr) = (peer.clone(), *addr);
+            thread::spawn(move || write_to_peer(&name, addr, messages_rx));
+            (peer.clone(), messages)
+        })
+        .collect();
+
+    let init = Message {
+        src: String::from(CLUSTER),
+        dest: id.to_string(),
+        body: Body {
+            specific_fields: SpecificBodyFields::Init {
+                node_id: id.to_string(),
+                node_ids: config.nodes.keys().cloned().collect(),
+            },
+            msg_id: Some(0),
+            in_reply_to: None,
+        },
+    };
+    let mut outputs = node.handle(init, Instant::now());
+    loop {
+        for out in outputs {
+            match out {
+                Output::Send(message) if message.dest == CLUSTER => {}
+                Output::Send(message) => match peers.get(&message.dest) {
+                    Some(peer) => peer.send(message).map_err(|_| stopped("peer writer"))?,
+                    None => clients
+                        .send(Outgoing::Message(message))
+                        .map_err(|_| stopped("client writer"))?,
+                },
+                Output::Timer { id, deadline } => {
+                    timers.send((deadline, id)).map_err(|_| stopped("timer"))?
+                }
+            }
+        }
+        outputs = match rx.recv().map_err(|_| stopped("listener and timer"))? {
+            Event::Line(Ok(line)) => runtime::handle_line(&mut node, &line),
+            Event::Timer(id) => node.fire(id, Instant::now()),
+            // connections going away is the readers' business
+            Event::Line(Err(_)) | Event::End => Vec::new(),
+        };
+    }
+}
+
+fn stopped(thread: &str) -> io::Error {
+    io::Error::other(format!("the {thread} thread stopped"))
+}
+
+// Pass on every line read from `stream`, and have the writer answer clients over it
+fn read_connection(
+    stream: TcpStream,
+    peers: HashSet<String>,
+    lines: Sender<Event>,
+    clients: Sender<Outgoing>,
+) {
+    let from = stream.peer_addr().ok();
+    let mut known = HashSet::new();
+    let reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) => return eprintln!("FAILED TO READ FROM {from:?}: {e}"),
+    };
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return eprintln!("CONNECTION FROM {from:?} FAILED: {e}"),
+        };
+        // registered before the node sees the line, so before it can reply
+        if let Some((src, _)) = protocol::sender(&line)
+            && !peers.contains(&src)
+            && known.insert(src.clone())
+            && let Ok(writer) = stream.try_clone()
+        {
+            eprintln!("CLIENT {src} CONNECTED FROM {from:?}");
+            if clients.send(Outgoing::Client(src, writer)).is_err() {
+                return;
+            }
+        }
+        if lines.send(Event::Line(Ok(line))).is_err() {
+            return;
+        }
+    }
+}
+
+// A connection we write to, or when we last failed to open one
+enum Connection {
+    Open(BufWriter<TcpStream>),
+    Failed(Instant),
+}
+
+// Send `peer` everything for it, connecting the first time there's something to
+// send and again after losing the connection, flushing once there's nothing left
+// waiting
+fn write_to_peer(peer: &str, addr: SocketAddr, messages: Receiver<Message>) {
+    let mut connection = None;
+    while let Ok(message) = messages.recv() {
+        for message in std::iter::once(message).chain(messages.try_iter()) {
+            if let Some(Connection::Failed(at)) = connection
+                && at.elapsed() < RECONNECT_DELAY
+            {
+                continue;
+            }
+            if !matches!(connection, Some(Connection::Open(_))) {
+                connection = Some(match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                    Ok(stream) => Connection::Open(BufWriter::new(stream)),
+                    Err(e) => {
+                        eprintln!("FAILED TO CONNECT TO {peer} AT {addr}: {e}");
+                        Connection::Failed(Instant::now())
+                    }
+                });
+            }
+            if let Some(Connection::Open(writer)) = &mut connection
+                && let Err(e) = writeln!(writer, "{:}", json!(message))
+            {
+                eprintln!("LOST CONNECTION TO {peer}: {e}");
+                connection = None;
+            }
+        }
+        if let Some(Connection::Open(writer)) = &mut connection
+            && let Err(e) = writer.flush()
+        {
+            eprintln!("LOST CONNECTION TO {peer}: {e}");
+            connection = None;
+        }
+    }
+}
+
+// Answer clients over the connection they came in on, flushing once there's
+// nothing left waiting
+fn write_to_clients(messages: Receiver<Outgoing>) {
+    let mut connections: HashMap<String, BufWriter<TcpStream>> = HashMap::new();
+    while let Ok(outgoing) = messages.recv() {
+        let mut written = HashSet::new();
+        for outgoing in std::iter::once(outgoing).chain(messages.try_iter()) {
+            let message = match outgoing {
+                Outgoing::Message(message) => message,
+                Outgoing::Client(client, stream) => {
+                    connections.insert(client, BufWriter::new(stream));
+                    continue;
+                }
+            };
+            let dest = message.dest.clone();
+            let Some(writer) = connections.get_mut(&dest) else {
+                eprintln!("NO ROUTE TO {dest}");
+                continue;
+            };
+            if let Err(e) = writeln!(writer, "{:}", json!(message)) {
+                eprintln!("LOST CONNECTION TO {dest}: {e}");
+                connections.remove(&dest);
+                continue;
+            }
+            written.insert(dest);
+        }
+        for dest in written {
+            if let Some(writer) = connections.get_mut(&dest)
+                && let Err(e) = writer.flush()
+            {
+                eprintln!("LOST CONNECTION TO {dest}: {e}");
+                connections.remove(&dest);
+            }
+        }
+    }
+}
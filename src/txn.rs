@@ -0,0 +1,172 @@
+// Totally-available transactions over read/write registers
+// https://fly.io/dist-sys/6a/
+//
+// A transaction runs in one go on the node that received it, which on a single
+// node is enough to be serializable. With several nodes its writes are then
+// replicated asynchronously, and applied in one go on every other node, so a
+// transaction never observes part of another one (read committed, which covers
+// read uncommitted). Every write carries a version made of a lamport clock and
+// the id of the node that made it, so all nodes settle on the same last writer
+// for a key whatever order the replication lands in.
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    error::NodeError,
+    handler::{Handler, Unhandled},
+    node::{Node, RpcError},
+    protocol::{SpecificBodyFields, TxnOperation, VersionedWrite},
+    wal::Record,
+};
+
+// How long to wait before retrying a replication that wasn't acknowledged
+const REPLICATION_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+pub struct Txn {
+    registers: HashMap<usize, Register>,
+    clock: usize,
+}
+
+#[derive(Debug)]
+struct Register {
+    value: usize,
+    version: (usize, String),
+}
+
+impl Txn {
+    // Writes older than what the register already holds are dropped
+    pub(crate) fn apply(&mut self, write: VersionedWrite) {
+        self.clock = self.clock.max(write.version.0);
+        if let Some(register) = self.registers.get(&write.key)
+            && register.version >= write.version
+        {
+            return;
+        }
+        self.registers.insert(
+            write.key,
+            Register {
+                value: write.value,
+                version: write.version,
+            },
+        );
+    }
+}
+
+pub struct TxnHandler;
+
+impl Handler for TxnHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        node.with_state(|state: &mut Txn, node| {
+            match body {
+                SpecificBodyFields::Txn { txn } => state.run(node, src, msg_id, txn)?,
+                SpecificBodyFields::ReplicateTxn { writes } => {
+                    state.replicate(node, src, msg_id, writes)
+                }
+                other => return Err(Unhandled::Passed(other)),
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Txn {
+    fn run(
+        &mut self,
+        node: &mut Node,
+        client: String,
+        msg_id: Option<usize>,
+        txn: Vec<TxnOperation>,
+    ) -> Result<(), NodeError> {
+        self.clock += 1;
+        let version = (self.clock, node.id.clone());
+
+        let mut writes: HashMap<usize, VersionedWrite> = HashMap::new();
+        let mut completed = Vec::with_capacity(txn.len());
+        for (op, key, value) in txn {
+            match (op.as_str(), value) {
+                ("r", _) => {
+                    // our own writes are newer than anything in the registers
+                    let read = match writes.get(&key) {
+                        Some(write) => Some(write.value),
+                        None => self.registers.get(&key).map(|register| register.value),
+                    };
+                    completed.push((op, key, read));
+                }
+                ("w", Some(value)) => {
+                    let write = VersionedWrite {
+                        key,
+                        value,
+                        version: version.clone(),
+                    };
+                    writes.insert(key, write);
+                    completed.push((op, key, Some(value)));
+                }
+                // the whole transaction is refused rather than applied in part
+                (op, value) => {
+                    return Err(NodeError::MalformedRequest(format!(
+                        "can't make sense of [{op}, {key}, {value:?}]"
+                    )));
+                }
+            }
+        }
+        if !writes.is_empty() {
+            node.persist(Record::Txn {
+                writes: writes.values().cloned().collect(),
+            });
+        }
+        for write in writes.values() {
+            self.apply(write.clone());
+        }
+        node.reply(client, msg_id, SpecificBodyFields::TxnOk { txn: completed });
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let writes: Vec<VersionedWrite> = writes.into_values().collect();
+        let peers: Vec<String> = node
+            .node_ids
+            .iter()
+            .filter(|peer| **peer != node.id)
+            .cloned()
+            .collect();
+        for peer in peers {
+            replicate_txn(node, peer, writes.clone());
+        }
+        Ok(())
+    }
+
+    fn replicate(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        writes: Vec<VersionedWrite>,
+    ) {
+        node.persist(Record::Txn {
+            writes: writes.clone(),
+        });
+        for write in writes {
+            self.apply(write);
+        }
+        node.reply(src, msg_id, SpecificBodyFields::ReplicateTxnOk);
+    }
+}
+
+fn replicate_txn(node: &mut Node, peer: String, writes: Vec<VersionedWrite>) {
+    let body = SpecificBodyFields::ReplicateTxn {
+        writes: writes.clone(),
+    };
+    node.rpc(peer.clone(), body, move |node, reply| {
+        if let Err(RpcError::Timeout) = reply {
+            node.schedule_once(REPLICATION_RETRY_DELAY, move |node| {
+                replicate_txn(node, peer, writes)
+            });
+        }
+    });
+}
@@ -0,0 +1,49 @@
+// Globally unique ids
+// https://fly.io/dist-sys/2/
+//
+// Every node makes its own, see id.rs, so there's nothing to coordinate.
+use crate::{
+    error::NodeError,
+    handler::{Handler, Unhandled},
+    id::IdGenerator,
+    node::Node,
+    protocol::SpecificBodyFields,
+};
+
+pub struct UniqueIdsHandler;
+
+impl Handler for UniqueIdsHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        if !matches!(body, SpecificBodyFields::Generate) {
+            return Err(Unhandled::Passed(body));
+        }
+        // what init left us with, nothing before it
+        let id = node.with_state(
+            |ids: &mut Option<Result<IdGenerator, NodeError>>, _| match ids {
+                Some(Ok(ids)) => Ok(ids.generate()),
+                Some(Err(e)) => Err(e.clone()),
+                None => Err(NodeError::Crash(String::from("no ids to make before init"))),
+            },
+        )?;
+        let id = id.to_string();
+        node.reply(src, msg_id, SpecificBodyFields::GenerateOk { id });
+        Ok(())
+    }
+
+    // every node gets the same list so our place in it is ours alone. A node with
+    // no room left for its ids still serves the rest of its workload, and refuses
+    // to make ids another node could make too.
+    fn init(&self, node: &mut Node) {
+        let ids = IdGenerator::new(node.index());
+        if let Err(e) = &ids {
+            eprintln!("NOT MAKING IDS: {e}");
+        }
+        node.with_state(|state: &mut Option<Result<IdGenerator, NodeError>>, _| *state = Some(ids));
+    }
+}
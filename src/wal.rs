@@ -0,0 +1,264 @@
+// Write-ahead log, so that a node coming back from a crash still has what it
+// acknowledged before it
+//
+// Every change to the state of a workload is appended as a JSON line before the
+// node answers anything that depends on it, and replayed in order when the node is
+// started again with the same file. What the node only keeps to get things done,
+// like pending requests and timers, isn't logged: it's redone from what was.
+//
+// A crash halfway through an append leaves a torn last line, without its newline,
+// which is dropped on replay. A whole line that doesn't parse is corruption and
+// refuses to open instead. The file is never trimmed, raft's snapshots only make
+// replay shorter.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    broadcast::Broadcast,
+    counter::Counter,
+    kafka::Kafka,
+    node::Node,
+    protocol::{LogEntry, VersionedWrite},
+    raft::Raft,
+    txn::Txn,
+};
+
+// When appended records are flushed to the disk itself rather than left to the OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    // before anything is answered, nothing acknowledged is ever lost
+    Always,
+    // at most this long after they're appended, what came since is lost on a
+    // power failure but not when only the process crashes
+    Every(Duration),
+    // whenever the OS sees fit
+    Never,
+}
+
+impl Fsync {
+    // Read from the FLYDIS_FSYNC variable, `always`, `never` or a period in
+    // milliseconds
+    pub fn from_env() -> Self {
+        match std::env::var("FLYDIS_FSYNC").as_deref() {
+            Ok("always") | Err(_) => Fsync::Always,
+            Ok("never") => Fsync::Never,
+            Ok(other) => match other.parse() {
+                Ok(millis) => Fsync::Every(Duration::from_millis(millis)),
+                Err(_) => {
+                    eprintln!("Unknown fsync policy {other}, defaulting to always");
+                    Fsync::Always
+                }
+            },
+        }
+    }
+}
+
+// A change to the state of one of the workloads
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Record {
+    Broadcast {
+        values: Vec<usize>,
+    },
+    Topology {
+        neighbours: Vec<String>,
+    },
+    Counter {
+        total: usize,
+    },
+    KafkaEntry {
+        key: String,
+        offset: usize,
+        msg: usize,
+    },
+    KafkaCommit {
+        offsets: HashMap<String, usize>,
+    },
+    Txn {
+        writes: Vec<VersionedWrite>,
+    },
+    RaftState {
+        term: usize,
+        voted_for: Option<String>,
+    },
+    // the log from `index` on is now `entries`
+    RaftEntries {
+        index: usize,
+        entries: Vec<LogEntry>,
+    },
+    RaftSnapshot {
+        index: usize,
+        term: usize,
+        data: HashMap<String, Value>,
+    },
+}
+
+pub(crate) struct Wal {
+    file: File,
+    fsync: Fsync,
+    synced_at: Instant,
+    // whether anything was appended since the last sync
+    dirty: bool,
+}
+
+impl Wal {
+    // Open the log at `path`, creating it if needed, along with every record it
+    // already holds
+    fn open(path: &Path, fsync: Fsync) -> io::Result<(Self, Vec<Record>)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut records = Vec::new();
+        // bytes up to the end of the last whole record
+        let mut valid = 0;
+        let mut reader = BufReader::new(&file);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if !line.ends_with(b"\n") {
+                // the last line, torn if there's anything in it
+                break;
+            }
+            // a whole line was written out in full, so it not parsing is corruption
+            // rather than a crash and dropping it would lose every record after it
+            let record = serde_json::from_slice(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupt record at byte {valid} of {}: {e}", path.display()),
+                )
+            })?;
+            records.push(record);
+            valid += read as u64;
+        }
+        let len = file.metadata()?.len();
+        if valid < len {
+            eprintln!("DROPPING {} BYTES OF TORN RECORD", len - valid);
+            file.set_len(valid)?;
+            file.sync_all()?;
+        }
+        let wal = Wal {
+            file,
+            fsync,
+            synced_at: Instant::now(),
+            dirty: false,
+        };
+        Ok((wal, records))
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        // a single write so that a crash can only ever tear the last record
+        self.file.write_all(&line)?;
+        self.dirty = true;
+        match self.fsync {
+            Fsync::Always => self.sync(),
+            Fsync::Every(period) if self.synced_at.elapsed() >= period => self.sync(),
+            Fsync::Every(_) | Fsync::Never => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+
+    // How often to sync what was appended in the meantime, if at all
+    pub(crate) fn sync_period(&self) -> Option<Duration> {
+        match self.fsync {
+            Fsync::Every(period) => Some(period),
+            Fsync::Always | Fsync::Never => None,
+        }
+    }
+}
+
+impl Node {
+    // Log every change to `path`, after replaying whatever is logged there already
+    pub fn with_wal(mut self, path: impl AsRef<Path>, fsync: Fsync) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (wal, records) = Wal::open(path, fsync)?;
+        eprintln!(
+            "REPLAYING {} RECORDS FROM {}",
+            records.len(),
+            path.display()
+        );
+        for record in records {
+            self.replay(record);
+        }
+        self.wal = Some(wal);
+        Ok(self)
+    }
+
+    // Every record goes to the state of the workload that logged it
+    fn replay(&mut self, record: Record) {
+        match record {
+            Record::Broadcast { values } => {
+                self.with_state(|broadcast: &mut Broadcast, _| broadcast.restore_values(values))
+            }
+            Record::Topology { neighbours } => self.with_state(|broadcast: &mut Broadcast, _| {
+                broadcast.restore_neighbours(neighbours)
+            }),
+            Record::Counter { total } => {
+                self.with_state(|counter: &mut Counter, _| counter.restore(total))
+            }
+            Record::KafkaEntry { key, offset, msg } => {
+                self.with_state(|kafka: &mut Kafka, _| kafka.insert_entry(key, offset, msg))
+            }
+            Record::KafkaCommit { offsets } => {
+                self.with_state(|kafka: &mut Kafka, _| kafka.commit(offsets))
+            }
+            Record::Txn { writes } => self.with_state(|txn: &mut Txn, _| {
+                for write in writes {
+                    txn.apply(write);
+                }
+            }),
+            Record::RaftState { term, voted_for } => {
+                self.with_state(|raft: &mut Raft, _| raft.restore_state(term, voted_for))
+            }
+            Record::RaftEntries { index, entries } => {
+                self.with_state(|raft: &mut Raft, _| raft.restore_entries(index, entries))
+            }
+            Record::RaftSnapshot { index, term, data } => {
+                self.with_state(|raft: &mut Raft, _| raft.install(index, term, data))
+            }
+        }
+    }
+
+    // Whether changes are logged, to skip building records that are costly to
+    pub(crate) fn persisting(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    // A node that can't log what it's about to acknowledge can't be trusted to
+    // come back with it, so it stops right there, like it would on a crash
+    pub(crate) fn persist(&mut self, record: Record) {
+        if let Some(wal) = &mut self.wal
+            && let Err(e) = wal.append(&record)
+        {
+            panic!("FAILED TO APPEND {record:?} TO THE WAL: {e}");
+        }
+    }
+
+    pub(crate) fn sync_wal(&mut self) {
+        if let Some(wal) = &mut self.wal
+            && let Err(e) = wal.sync()
+        {
+            panic!("FAILED TO SYNC THE WAL: {e}");
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// The maelstrom workloads this crate serves, and the handlers serving each.
+//
+// The broadcast and g-counter workloads both send a bare `read`, so which one we
+// serve is picked when the node starts, and with it the handlers the node gets.
+// Serving lin-kv means running raft, which we'd rather not do for any other workload.
+use std::rc::Rc;
+
+use crate::{
+    broadcast::BroadcastHandler, counter::CounterHandler, echo::EchoHandler, handler::Handler,
+    kafka::KafkaHandler, kv::KvService, node::Node, raft::LinKvHandler, txn::TxnHandler,
+    unique_ids::UniqueIdsHandler,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Workload {
+    #[default]
+    Broadcast,
+    Counter,
+    Kafka,
+    Txn,
+    LinKv,
+}
+
+impl Workload {
+    // Read from the FLYDIS_WORKLOAD variable, named like maelstrom's workloads
+    pub fn from_env() -> Self {
+        match std::env::var("FLYDIS_WORKLOAD").as_deref() {
+            Ok("g-counter") => Workload::Counter,
+            Ok("kafka") => Workload::Kafka,
+            Ok("txn-rw-register") => Workload::Txn,
+            Ok("lin-kv") => Workload::LinKv,
+            Ok("broadcast") | Err(_) => Workload::Broadcast,
+            Ok(other) => {
+                eprintln!("Unknown workload {other}, defaulting to broadcast");
+                Workload::Broadcast
+            }
+        }
+    }
+
+    // Echo and unique ids come with every workload
+    pub fn handlers(self) -> Vec<Rc<dyn Handler>> {
+        let workload: Rc<dyn Handler> = match self {
+            Workload::Broadcast => Rc::new(BroadcastHandler),
+            Workload::Counter => Rc::new(CounterHandler),
+            Workload::Kafka => Rc::new(KafkaHandler),
+            Workload::Txn => Rc::new(TxnHandler),
+            Workload::LinKv => Rc::new(LinKvHandler),
+        };
+        vec![Rc::new(EchoHandler), Rc::new(UniqueIdsHandler), workload]
+    }
+
+    // The kv services maelstrom has to run next to the nodes for it to work
+    pub fn kv_services(self) -> &'static [KvService] {
+        match self {
+            Workload::Counter => &[KvService::Seq],
+            Workload::Broadcast | Workload::Kafka | Workload::Txn | Workload::LinKv => &[],
+        }
+    }
+}
+
+impl Node {
+    // A node serving the maelstrom workload picked when it starts
+    pub fn new(workload: Workload) -> Self {
+        Self::with_handlers(workload.handlers())
+    }
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::new(Workload::default())
+    }
+}
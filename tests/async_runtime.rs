@@ -0,0 +1,105 @@
+#![cfg(feature = "async")]
+
+use flydis::{
+    async_runtime::AsyncRuntime,
+    kv::{KvError, KvService},
+    node::{Node, RpcError},
+    protocol::{Message, SpecificBodyFields},
+    workload::Workload,
+};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, SimplexStream};
+
+type Output = Lines<BufReader<ReadHalf<SimplexStream>>>;
+
+async fn next(output: &mut Output) -> Message {
+    let line = output.next_line().await.unwrap().unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn message(src: &str, body: serde_json::Value) -> String {
+    let line = json!({"src": src, "dest": "n0", "body": body});
+    format!("{line}\n")
+}
+
+#[tokio::test]
+async fn async_runtime_serves_handlers_and_awaits_replies() {
+    let (input, mut to_node) = tokio::io::simplex(1 << 16);
+    let (output, from_node) = tokio::io::simplex(1 << 16);
+    let mut output = BufReader::new(output).lines();
+    let runtime = AsyncRuntime::new(Node::new(Workload::Broadcast));
+    let node = runtime.handle();
+
+    let client = async move {
+        let init = json!({"type": "init", "node_id": "n0", "node_ids": ["n0", "n1"], "msg_id": 0});
+        to_node
+            .write_all(message("c0", init).as_bytes())
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut output).await.body.specific_fields,
+            SpecificBodyFields::InitOk
+        ));
+        let echo = json!({"type": "echo", "echo": "hi", "msg_id": 1});
+        to_node
+            .write_all(message("c1", echo).as_bytes())
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut output).await.body.specific_fields,
+            SpecificBodyFields::EchoOk { .. }
+        ));
+
+        // a request made through the handle waits for the peer's answer
+        let rpc = node.rpc(
+            String::from("n1"),
+            SpecificBodyFields::Echo {
+                echo: String::from("there?"),
+            },
+        );
+        let peer = async {
+            let request = next(&mut output).await;
+            assert_eq!(request.dest, "n1");
+            let msg_id = request.body.msg_id.unwrap();
+            let echo =
+                json!({"type": "echo_ok", "echo": "here", "msg_id": 0, "in_reply_to": msg_id});
+            to_node
+                .write_all(message("n1", echo).as_bytes())
+                .await
+                .unwrap();
+        };
+        let (reply, ()) = tokio::join!(rpc, peer);
+        assert!(matches!(
+            reply.unwrap().body.specific_fields,
+            SpecificBodyFields::EchoOk { echo } if echo == "here"
+        ));
+
+        let read = node.kv_read::<usize>(KvService::Seq, "counter");
+        let service = async {
+            let request = next(&mut output).await;
+            assert_eq!(request.dest, "seq-kv");
+            let msg_id = request.body.msg_id.unwrap();
+            let error =
+                json!({"type": "error", "code": 20, "text": "not found", "in_reply_to": msg_id});
+            to_node
+                .write_all(message("seq-kv", error).as_bytes())
+                .await
+                .unwrap();
+        };
+        let (read, ()) = tokio::join!(read, service);
+        assert!(matches!(read, Err(KvError::KeyDoesNotExist)));
+
+        // nobody answers this one, so the node's own timer gives up on it
+        let rpc = node.rpc(String::from("n1"), SpecificBodyFields::Generate);
+        let (reply, _) = tokio::join!(rpc, next(&mut output));
+        assert!(matches!(reply, Err(RpcError::Timeout)));
+
+        // the input running out stops the node
+        to_node.shutdown().await.unwrap();
+        let stopped = node.rpc(String::from("n1"), SpecificBodyFields::Generate);
+        assert!(matches!(stopped.await, Err(RpcError::Timeout)));
+    };
+
+    let (served, ()) = tokio::join!(runtime.run(BufReader::new(input), from_node), client);
+    served.unwrap();
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashSet, time::Duration};
+
+use flydis::{
+    check::{self, Op, Operation, Outcome},
+    protocol::SpecificBodyFields,
+};
+
+fn operation(op: Op, node: &str, invoked: u64, reply: Option<SpecificBodyFields>) -> Operation {
+    let invoked = Duration::from_millis(invoked);
+    Operation {
+        op,
+        node: node.to_string(),
+        invoked,
+        completed: reply.as_ref().map(|_| invoked + Duration::from_millis(1)),
+        outcome: reply.map_or(Outcome::TimedOut, Outcome::Ok),
+    }
+}
+
+fn read(op: Op, node: &str, invoked: u64, messages: &[usize]) -> Operation {
+    let body = SpecificBodyFields::ReadOk {
+        messages: Some(messages.iter().copied().collect::<HashSet<_>>()),
+        value: None,
+    };
+    operation(op, node, invoked, Some(body))
+}
+
+fn generated(node: &str, invoked: u64, id: &str) -> Operation {
+    let body = SpecificBodyFields::GenerateOk { id: id.to_string() };
+    operation(Op::Generate, node, invoked, Some(body))
+}
+
+#[test]
+fn unique_ids_reports_duplicates() {
+    let history = [
+        generated("n0", 0, "n0-1"),
+        generated("n1", 1, "n1-1"),
+        generated("n1", 2, "n0-1"),
+    ];
+    let verdict = check::unique_ids(&history);
+    assert_eq!(verdict.errors.len(), 1, "{:?}", verdict.errors);
+    assert!(verdict.errors[0].contains("n0-1"));
+
+    assert!(check::unique_ids(&history[..2]).is_valid());
+}
+
+#[test]
+fn broadcast_accepts_stale_reads_but_not_lost_values() {
+    let nodes = [String::from("n0"), String::from("n1")];
+    let broadcast = |value, invoked| {
+        operation(
+            Op::Broadcast(value),
+            "n0",
+            invoked,
+            Some(SpecificBodyFields::BroadcastOk),
+        )
+    };
+    let mut history = vec![
+        broadcast(1, 0),
+        broadcast(2, 10),
+        // never acknowledged, so it may or may not show up
+        operation(Op::Broadcast(3), "n0", 20, None),
+        read(Op::Read, "n1", 30, &[1]),
+        read(Op::FinalRead, "n0", 100, &[1, 2, 3]),
+        read(Op::FinalRead, "n1", 100, &[1, 2]),
+    ];
+    let verdict = check::broadcast(&history, &nodes);
+    assert!(verdict.is_valid(), "{:?}", verdict.errors);
+    assert!(
+        verdict
+            .notes
+            .iter()
+            .any(|note| note.contains("1 stale read"))
+    );
+
+    history[5] = read(Op::FinalRead, "n1", 100, &[1]);
+    let verdict = check::broadcast(&history, &nodes);
+    assert_eq!(verdict.errors, ["n1 lost 1 acknowledged value(s): {2}"]);
+}
+
+#[test]
+fn broadcast_rejects_made_up_values_and_missing_final_reads() {
+    let nodes = [String::from("n0"), String::from("n1")];
+    let history = [
+        operation(
+            Op::Broadcast(1),
+            "n0",
+            0,
+            Some(SpecificBodyFields::BroadcastOk),
+        ),
+        read(Op::FinalRead, "n0", 100, &[1, 42]),
+    ];
+    let verdict = check::broadcast(&history, &nodes);
+    assert_eq!(verdict.errors.len(), 2, "{:?}", verdict.errors);
+    assert!(verdict.errors[0].contains("never broadcast: {42}"));
+    assert_eq!(verdict.errors[1], "n1 never answered its final read");
+}
@@ -0,0 +1,45 @@
+// Fixtures shared by the tests that drive a single node by hand, always n0
+use std::time::Instant;
+
+use flydis::{
+    node::{Node, Output},
+    protocol::{Body, Message, SpecificBodyFields},
+};
+
+pub fn message(src: &str, msg_id: usize, body: SpecificBodyFields) -> Message {
+    Message {
+        src: src.to_string(),
+        dest: String::from("n0"),
+        body: Body {
+            specific_fields: body,
+            msg_id: Some(msg_id),
+            in_reply_to: None,
+        },
+    }
+}
+
+// Only what answers this request from a client, a restarted raft leader also
+// answers the requests it applies again
+pub fn request(
+    node: &mut Node,
+    msg_id: usize,
+    body: SpecificBodyFields,
+) -> Vec<SpecificBodyFields> {
+    node.handle(message("c1", msg_id, body), Instant::now())
+        .into_iter()
+        .filter_map(|output| match output {
+            Output::Send(message) if message.body.in_reply_to == Some(msg_id) => {
+                Some(message.body.specific_fields)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// A cluster of n0 alone
+pub fn init() -> SpecificBodyFields {
+    SpecificBodyFields::Init {
+        node_id: String::from("n0"),
+        node_ids: vec![String::from("n0")],
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::{init, request};
+use flydis::{
+    error::NodeError, id::MAX_NODES, node::Node, protocol::SpecificBodyFields, workload::Workload,
+};
+
+fn error_code(replies: &[SpecificBodyFields]) -> Option<u32> {
+    match replies {
+        [SpecificBodyFields::Error { code, .. }] => Some(*code),
+        _ => None,
+    }
+}
+
+#[test]
+fn unexpected_messages_are_answered_with_errors() {
+    let mut node = Node::new(Workload::Broadcast);
+    assert!(matches!(
+        request(&mut node, 0, init())[..],
+        [SpecificBodyFields::InitOk]
+    ));
+    // a second init
+    assert_eq!(error_code(&request(&mut node, 1, init())), Some(12));
+    // a reply that doesn't answer anything
+    assert_eq!(
+        error_code(&request(&mut node, 2, SpecificBodyFields::TopologyOk)),
+        Some(10)
+    );
+    // lin-kv when the node serves broadcast
+    let write = SpecificBodyFields::Write {
+        key: 0.into(),
+        value: 1.into(),
+    };
+    assert_eq!(error_code(&request(&mut node, 3, write)), Some(10));
+    // and it's still up
+    let echo = SpecificBodyFields::Echo {
+        echo: String::from("still here"),
+    };
+    assert!(matches!(
+        request(&mut node, 4, echo)[..],
+        [SpecificBodyFields::EchoOk { .. }]
+    ));
+}
+
+#[test]
+fn nodes_past_what_ids_make_room_for_still_serve_their_workload() {
+    let mut node = Node::new(Workload::Broadcast);
+    let node_ids: Vec<String> = (0..=MAX_NODES).map(|i| format!("n{i}")).collect();
+    let init = SpecificBodyFields::Init {
+        node_id: node_ids[MAX_NODES].clone(),
+        node_ids,
+    };
+    assert!(matches!(
+        request(&mut node, 0, init)[..],
+        [SpecificBodyFields::InitOk]
+    ));
+    let broadcast = SpecificBodyFields::Broadcast {
+        broadcast_message: 7,
+    };
+    request(&mut node, 1, broadcast);
+    assert!(matches!(
+        &request(&mut node, 2, SpecificBodyFields::Read { key: None })[..],
+        [SpecificBodyFields::ReadOk { messages: Some(messages), .. }] if messages.contains(&7)
+    ));
+    // only it doesn't make ids another node could make too
+    let replies = request(&mut node, 3, SpecificBodyFields::Generate);
+    assert_eq!(error_code(&replies), Some(13));
+}
+
+#[test]
+fn malformed_transactions_are_refused_whole() {
+    let mut node = Node::new(Workload::Txn);
+    request(&mut node, 0, init());
+    let txn = vec![
+        (String::from("w"), 1, Some(1)),
+        (String::from("w"), 2, None),
+    ];
+    let replies = request(&mut node, 1, SpecificBodyFields::Txn { txn });
+    assert_eq!(error_code(&replies), Some(12));
+
+    // the write before the bad operation didn't go through
+    let read = vec![(String::from("r"), 1, None)];
+    let replies = request(&mut node, 2, SpecificBodyFields::Txn { txn: read });
+    let [SpecificBodyFields::TxnOk { txn }] = &replies[..] else {
+        panic!("{replies:?}");
+    };
+    assert_eq!(txn[0].2, None);
+}
+
+#[test]
+fn codes_round_trip() {
+    for code in [0, 1, 10, 11, 12, 13, 14, 20, 21, 22, 30, 1000] {
+        let error = NodeError::from_code(code, String::from("oops"));
+        assert_eq!(error.code(), code);
+        assert_eq!(error.is_definite(), !matches!(code, 0 | 13));
+    }
+}
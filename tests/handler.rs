@@ -0,0 +1,137 @@
+mod common;
+
+use std::{cell::Cell, rc::Rc};
+
+use common::{init, request};
+use flydis::{
+    echo::EchoHandler,
+    error::NodeError,
+    handler::{Handler, Unhandled},
+    node::Node,
+    protocol::{CustomBody, SpecificBodyFields},
+    workload::Workload,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Ping {
+    round: usize,
+}
+
+impl CustomBody for Ping {
+    const TYPE: &'static str = "ping";
+}
+
+#[derive(Serialize, Deserialize)]
+struct Pong {
+    round: usize,
+    pings: usize,
+}
+
+impl CustomBody for Pong {
+    const TYPE: &'static str = "pong";
+}
+
+// Answers pings with how many it got so far
+#[derive(Default)]
+struct PingHandler {
+    pings: Cell<usize>,
+    inits: Cell<usize>,
+}
+
+impl Handler for PingHandler {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        let Some(ping) = Ping::parse(&body) else {
+            return Err(Unhandled::Passed(body));
+        };
+        let ping = ping.map_err(|e| NodeError::MalformedRequest(e.to_string()))?;
+        self.pings.set(self.pings.get() + 1);
+        let pong = Pong {
+            round: ping.round,
+            pings: self.pings.get(),
+        };
+        node.reply(src, msg_id, pong.into_body());
+        Ok(())
+    }
+
+    fn init(&self, _node: &mut Node) {
+        self.inits.set(self.inits.get() + 1);
+    }
+}
+
+// Echoes everything back shouted
+struct LoudEcho;
+
+impl Handler for LoudEcho {
+    fn handle(
+        &self,
+        node: &mut Node,
+        src: String,
+        msg_id: Option<usize>,
+        body: SpecificBodyFields,
+    ) -> Result<(), Unhandled> {
+        let SpecificBodyFields::Echo { echo } = body else {
+            return Err(Unhandled::Passed(body));
+        };
+        let echo = echo.to_uppercase();
+        node.reply(src, msg_id, SpecificBodyFields::EchoOk { echo });
+        Ok(())
+    }
+}
+
+fn echo(echo: &str) -> SpecificBodyFields {
+    SpecificBodyFields::Echo {
+        echo: String::from(echo),
+    }
+}
+
+#[test]
+fn registered_handlers_serve_their_own_bodies() {
+    let pings = Rc::new(PingHandler::default());
+    let mut node = Node::with_handlers(vec![pings.clone()]);
+    node.register(EchoHandler);
+    request(&mut node, 0, init());
+    assert_eq!(pings.inits.get(), 1);
+
+    for round in 1..=2 {
+        let replies = request(&mut node, round, Ping { round }.into_body());
+        let [reply] = &replies[..] else {
+            panic!("expected a single pong, got {replies:?}");
+        };
+        let pong = Pong::parse(reply).unwrap().unwrap();
+        assert_eq!((pong.round, pong.pings), (round, round));
+    }
+    assert!(matches!(
+        request(&mut node, 3, echo("hi"))[..],
+        [SpecificBodyFields::EchoOk { .. }]
+    ));
+    // nobody registered for generate
+    assert!(matches!(
+        request(&mut node, 4, SpecificBodyFields::Generate)[..],
+        [SpecificBodyFields::Error { code: 10, .. }]
+    ));
+}
+
+#[test]
+fn the_first_handler_serving_a_request_gets_it() {
+    let mut handlers = Workload::Broadcast.handlers();
+    handlers.insert(0, Rc::new(LoudEcho));
+    let mut node = Node::with_handlers(handlers);
+    request(&mut node, 0, init());
+    match &request(&mut node, 1, echo("hi"))[..] {
+        [SpecificBodyFields::EchoOk { echo }] => assert_eq!(echo, "HI"),
+        replies => panic!("expected an echo, got {replies:?}"),
+    }
+    // and an unknown type is refused rather than ignored
+    let replies = request(&mut node, 2, Ping { round: 1 }.into_body());
+    assert!(matches!(
+        replies[..],
+        [SpecificBodyFields::Error { code: 10, .. }]
+    ));
+}
@@ -0,0 +1,71 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, SystemTime},
+};
+
+use flydis::id::{Id, IdGenerator, MAX_NODES};
+
+#[test]
+fn ids_stay_unique_and_ordered_when_the_clock_goes_back() {
+    let start = SystemTime::now();
+    let mut ids = IdGenerator::new(3).unwrap();
+    let mut made = Vec::new();
+    // a burst well over the sequence within a single millisecond, then the clock
+    // jumps a second back before moving forward again
+    for _ in 0..10_000 {
+        made.push(ids.generate_at(start));
+    }
+    for millis in 0..2000 {
+        made.push(ids.generate_at(start - Duration::from_secs(1) + Duration::from_millis(millis)));
+    }
+
+    assert!(made.is_sorted_by(|a, b| a < b));
+    assert!(made.iter().map(Id::numeric).is_sorted_by(|a, b| a < b));
+    assert!(made.iter().map(Id::sortable).is_sorted_by(|a, b| a < b));
+}
+
+#[test]
+fn ids_from_different_nodes_order_the_same_in_every_format() {
+    let now = SystemTime::now();
+    let (mut first, mut second) = (IdGenerator::new(1).unwrap(), IdGenerator::new(2).unwrap());
+    // a sequence further along on the lower node
+    let mut made: Vec<Id> = (0..5).map(|_| first.generate_at(now)).collect();
+    made.push(second.generate_at(now));
+    made.push(second.generate_at(now));
+
+    assert!(made.is_sorted_by(|a, b| a < b));
+    assert!(made.iter().map(Id::numeric).is_sorted_by(|a, b| a < b));
+    assert!(made.iter().map(Id::sortable).is_sorted_by(|a, b| a < b));
+    assert!(made.iter().map(Id::uuid).is_sorted_by(|a, b| a < b));
+}
+
+#[test]
+fn nodes_never_collide() {
+    let now = SystemTime::now();
+    let mut seen = HashSet::new();
+    for node in 0..5 {
+        let mut ids = IdGenerator::new(node).unwrap();
+        for _ in 0..1000 {
+            assert!(seen.insert(ids.generate_at(now).numeric()));
+        }
+    }
+}
+
+#[test]
+fn sortable_ids_are_uuid_v7() {
+    let mut ids = IdGenerator::new(1).unwrap();
+    let id = ids.generate_at(SystemTime::UNIX_EPOCH + Duration::from_millis(1_750_000_000_000));
+    let uuid = id.uuid();
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(&uuid[..13], "01977420-dc00");
+    // version then variant
+    assert_eq!(&uuid[14..15], "7");
+    assert!(matches!(&uuid[19..20], "8" | "9" | "a" | "b"));
+    assert_eq!(id.to_string(), id.numeric().to_string());
+}
+
+#[test]
+fn nodes_past_the_last_one_ids_make_room_for_are_refused() {
+    assert!(IdGenerator::new(MAX_NODES - 1).is_ok());
+    assert!(IdGenerator::new(MAX_NODES).is_err());
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use flydis::linearizable::{self, KeyError, KvOp, KvOperation, KvOutcome};
+use serde_json::json;
+
+fn op(client: &str, op: KvOp, invoked: u64, completed: u64, outcome: KvOutcome) -> KvOperation {
+    KvOperation {
+        client: client.to_string(),
+        key: String::from("0"),
+        op,
+        invoked: Duration::from_millis(invoked),
+        completed: Some(Duration::from_millis(completed)),
+        outcome,
+    }
+}
+
+fn write(client: &str, value: u64, invoked: u64, completed: u64) -> KvOperation {
+    op(
+        client,
+        KvOp::Write(json!(value)),
+        invoked,
+        completed,
+        KvOutcome::Ok,
+    )
+}
+
+fn read(client: &str, value: Option<u64>, invoked: u64, completed: u64) -> KvOperation {
+    let outcome = value.map_or(KvOutcome::KeyDoesNotExist, |v| KvOutcome::Read(json!(v)));
+    op(client, KvOp::Read, invoked, completed, outcome)
+}
+
+fn cas(client: &str, from: u64, to: u64, invoked: u64, completed: u64, ok: bool) -> KvOperation {
+    let outcome = if ok {
+        KvOutcome::Ok
+    } else {
+        KvOutcome::PreconditionFailed
+    };
+    let op_ = KvOp::Cas {
+        from: json!(from),
+        to: json!(to),
+    };
+    op(client, op_, invoked, completed, outcome)
+}
+
+#[test]
+fn concurrent_operations_can_be_reordered() {
+    let history = [
+        read("c1", None, 0, 5),
+        write("c1", 1, 10, 20),
+        // overlaps the write so it may see either value
+        read("c2", Some(1), 12, 14),
+        cas("c3", 1, 2, 15, 30, true),
+        read("c2", Some(1), 16, 18),
+        read("c1", Some(2), 25, 40),
+        cas("c2", 1, 3, 35, 45, false),
+    ];
+    let verdict = linearizable::check(&history);
+    assert!(verdict.is_valid(), "{:?}", verdict.errors);
+}
+
+#[test]
+fn stale_read_is_reported_with_a_minimal_window() {
+    let mut history = vec![write("c1", 1, 0, 10)];
+    // plenty of unrelated but valid operations before the problem
+    for i in 0..20 {
+        history.push(read("c2", Some(1), 20 + i * 10, 25 + i * 10));
+    }
+    history.push(write("c1", 2, 300, 310));
+    // the write of 2 completed before this read started
+    history.push(read("c2", Some(1), 320, 330));
+    history.push(read("c3", Some(2), 340, 350));
+
+    let Err(KeyError::NotLinearizable(window)) = linearizable::check_key(&history) else {
+        panic!("stale read went unnoticed");
+    };
+    assert_eq!(window.operations.len(), 2, "{window}");
+    assert_eq!(window.operations[0].op, KvOp::Write(json!(2)));
+    assert_eq!(window.operations[1].outcome, KvOutcome::Read(json!(1)));
+    assert_eq!(linearizable::check(&history).errors, [window.to_string()]);
+}
+
+#[test]
+fn timed_out_writes_may_or_may_not_have_happened() {
+    let mut lost = op("c1", KvOp::Write(json!(1)), 0, 0, KvOutcome::Unknown);
+    lost.completed = None;
+    // nothing ever saw it
+    let never = [lost.clone(), read("c2", None, 100, 110)];
+    assert!(linearizable::check(&never).is_valid());
+    // it landed well after it timed out
+    let late = [
+        lost.clone(),
+        read("c2", None, 100, 110),
+        read("c2", Some(1), 200, 210),
+    ];
+    assert!(linearizable::check(&late).is_valid());
+    // but it can't be undone once seen
+    let undone = [
+        lost,
+        read("c2", Some(1), 100, 110),
+        read("c2", None, 200, 210),
+    ];
+    assert!(!linearizable::check(&undone).is_valid());
+}
+
+#[test]
+fn impossible_outcomes_are_rejected() {
+    let history = [op(
+        "c1",
+        KvOp::Write(json!(1)),
+        0,
+        1,
+        KvOutcome::PreconditionFailed,
+    )];
+    assert!(matches!(
+        linearizable::check_key(&history),
+        Err(KeyError::Malformed(_))
+    ));
+}
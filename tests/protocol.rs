@@ -0,0 +1,120 @@
+use flydis::protocol::{Body, CustomBody, Message, SpecificBodyFields};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Gossip {
+    values: Vec<usize>,
+    round: usize,
+}
+
+impl CustomBody for Gossip {
+    const TYPE: &'static str = "gossip";
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Ping {}
+
+impl CustomBody for Ping {
+    const TYPE: &'static str = "ping";
+}
+
+#[test]
+fn custom_bodies_travel_as_plain_messages() {
+    let gossip = Gossip {
+        values: vec![1, 2],
+        round: 3,
+    };
+    let message = Message {
+        src: String::from("n0"),
+        dest: String::from("n1"),
+        body: Body {
+            specific_fields: gossip.into_body(),
+            msg_id: Some(7),
+            in_reply_to: None,
+        },
+    };
+    let sent = json!(message);
+    assert_eq!(
+        sent,
+        json!({
+            "src": "n0",
+            "dest": "n1",
+            "body": {"type": "gossip", "values": [1, 2], "round": 3, "msg_id": 7},
+        })
+    );
+
+    let received: Message = serde_json::from_value(sent).unwrap();
+    assert_eq!(received.body.msg_id, Some(7));
+    assert_eq!(received.body.specific_fields.type_name(), "GOSSIP");
+    let parsed = Gossip::parse(&received.body.specific_fields)
+        .unwrap()
+        .unwrap();
+    assert_eq!(parsed.values, [1, 2]);
+    assert!(Ping::parse(&received.body.specific_fields).is_none());
+
+    let ping = Ping {}.into_body();
+    assert_eq!(json!(ping), json!({"type": "ping"}));
+    assert_eq!(Ping::parse(&ping).unwrap().unwrap(), Ping {});
+}
+
+#[test]
+fn known_types_keep_their_variant() {
+    let body: Body =
+        serde_json::from_value(json!({"type": "echo", "echo": "hi", "msg_id": 1})).unwrap();
+    assert!(matches!(
+        body.specific_fields,
+        SpecificBodyFields::Echo { .. }
+    ));
+    // missing its echo
+    let body: Body = serde_json::from_value(json!({"type": "echo", "msg_id": 1})).unwrap();
+    assert!(matches!(
+        body.specific_fields,
+        SpecificBodyFields::Custom(custom) if custom.kind == "echo"
+    ));
+    // and a custom body under a known type is never mistaken for it
+    let mismatched = Gossip::parse(&SpecificBodyFields::Generate);
+    assert!(mismatched.is_none());
+}
+
+#[test]
+fn known_types_that_dont_parse_are_told_apart_from_unknown_ones() {
+    let parse = |body| serde_json::from_value::<SpecificBodyFields>(body).unwrap();
+    // a msg that isn't a number
+    let SpecificBodyFields::Custom(send) = parse(json!({"type": "send", "key": "k", "msg": "m"}))
+    else {
+        panic!("a bad send parsed");
+    };
+    assert!(send.is_malformed());
+    let SpecificBodyFields::Custom(gossip) = parse(json!({"type": "gossip", "values": []})) else {
+        panic!("gossip isn't one of ours");
+    };
+    assert!(!gossip.is_malformed());
+}
+
+#[test]
+fn type_names_are_what_goes_in_the_type_field() {
+    let bodies = [
+        SpecificBodyFields::InitOk,
+        SpecificBodyFields::Echo {
+            echo: String::from("hi"),
+        },
+        SpecificBodyFields::MultiBroadcastOk,
+        SpecificBodyFields::ListCommittedOffsets { keys: Vec::new() },
+        SpecificBodyFields::InstallSnapshotOk { term: 1 },
+    ];
+    for body in bodies {
+        let json = serde_json::to_value(&body).unwrap();
+        let kind = json["type"].as_str().unwrap();
+        assert_eq!(body.type_name(), kind.to_uppercase());
+
+        // and the same type with a field it can't have is known to be malformed
+        let mut bad = json.clone();
+        bad["term"] = json!("not a term");
+        bad["echo"] = json!(0);
+        bad["keys"] = json!(0);
+        if let SpecificBodyFields::Custom(custom) = serde_json::from_value(bad).unwrap() {
+            assert!(custom.is_malformed(), "{kind} isn't known");
+        }
+    }
+}
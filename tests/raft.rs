@@ -0,0 +1,243 @@
+use std::{collections::HashMap, time::Duration};
+
+use flydis::{
+    check::{Op, Operation, Outcome},
+    linearizable::{self, KvOp},
+    protocol::SpecificBodyFields,
+    rng::Rng,
+    sim::{Latency, Partition, Sim, SimConfig},
+    workload::Workload,
+};
+use serde_json::json;
+
+const CLIENTS: usize = 6;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Clients each keep a single random operation going against a random node, the
+// way maelstrom does, and the history is checked once they're done
+fn run_lin_kv(sim: &mut Sim, duration: Duration, rng: &mut Rng) -> Vec<Operation> {
+    let nodes = sim.node_ids();
+    let mut history = Vec::new();
+    // client -> (msg_id, operation)
+    let mut pending: HashMap<String, (usize, Operation)> = HashMap::new();
+    let mut seen = sim.log().len();
+    let end = sim.elapsed() + duration;
+
+    while sim.elapsed() < end {
+        for client in (1..=CLIENTS).map(|i| format!("c{i}")) {
+            if pending.contains_key(&client) {
+                continue;
+            }
+            let node = nodes[rng.next_u64() as usize % nodes.len()].clone();
+            let key = json!(rng.next_u64() % 3);
+            let (op, body) = match rng.next_u64() % 3 {
+                0 => (
+                    KvOp::Read,
+                    SpecificBodyFields::Read {
+                        key: Some(key.clone()),
+                    },
+                ),
+                1 => {
+                    let value = json!(rng.next_u64() % 5);
+                    let body = SpecificBodyFields::Write {
+                        key: key.clone(),
+                        value: value.clone(),
+                    };
+                    (KvOp::Write(value), body)
+                }
+                _ => {
+                    let (from, to) = (json!(rng.next_u64() % 5), json!(rng.next_u64() % 5));
+                    let body = SpecificBodyFields::Cas {
+                        key: key.clone(),
+                        from: from.clone(),
+                        to: to.clone(),
+                        create_if_not_exists: false,
+                    };
+                    (KvOp::Cas { from, to }, body)
+                }
+            };
+            let operation = Operation {
+                op: Op::Kv { key, op },
+                node: node.clone(),
+                invoked: sim.elapsed(),
+                completed: None,
+                outcome: Outcome::TimedOut,
+            };
+            let msg_id = sim.client(&client, &node, body);
+            pending.insert(client, (msg_id, operation));
+        }
+
+        sim.run_for(Duration::from_millis(10));
+
+        for delivery in &sim.log()[seen..] {
+            let message = &delivery.message;
+            if let Some((msg_id, _)) = pending.get(&message.dest)
+                && message.body.in_reply_to == Some(*msg_id)
+            {
+                let (_, mut operation) = pending.remove(&message.dest).unwrap();
+                operation.completed = Some(delivery.at);
+                operation.outcome = match message.body.specific_fields.clone() {
+                    SpecificBodyFields::Error { code, text } => Outcome::Failed { code, text },
+                    body => Outcome::Ok(body),
+                };
+                history.push(operation);
+            }
+        }
+        seen = sim.log().len();
+
+        let now = sim.elapsed();
+        pending.retain(|_, (_, operation)| {
+            if now - operation.invoked < CLIENT_TIMEOUT {
+                return true;
+            }
+            history.push(operation.clone());
+            false
+        });
+    }
+    history.extend(pending.into_values().map(|(_, operation)| operation));
+    history
+}
+
+fn successes(history: &[Operation]) -> usize {
+    history
+        .iter()
+        .filter(|operation| matches!(operation.outcome, Outcome::Ok(_)))
+        .count()
+}
+
+#[test]
+fn single_node_serves_lin_kv() {
+    let mut sim = Sim::new(1, Workload::LinKv);
+    let history = run_lin_kv(&mut sim, Duration::from_secs(5), &mut Rng::new(0));
+    let verdict = linearizable::lin_kv(&history);
+    assert!(verdict.is_valid(), "{:#?}", verdict.errors);
+    assert!(successes(&history) > history.len() / 2);
+}
+
+#[test]
+fn lin_kv_stays_linearizable_through_partitions() {
+    for seed in 0..5 {
+        let config = SimConfig {
+            seed,
+            latency: Latency::Uniform {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(20),
+            },
+            drop_rate: 0.05,
+            duplicate_rate: 0.05,
+            ..SimConfig::default()
+        };
+        let mut sim = Sim::with_config(5, Workload::LinKv, config);
+        sim.partition_periodically(Duration::from_secs(2), Duration::from_secs(12));
+        let history = run_lin_kv(&mut sim, Duration::from_secs(15), &mut Rng::new(seed));
+
+        let verdict = linearizable::lin_kv(&history);
+        assert!(verdict.is_valid(), "seed {seed}: {:#?}", verdict.errors);
+        // a majority is up most of the time, so plenty should have gone through
+        assert!(
+            successes(&history) > history.len() / 4,
+            "seed {seed}: only {} of {} operations succeeded",
+            successes(&history),
+            history.len()
+        );
+    }
+}
+
+#[test]
+fn lagging_follower_catches_up_from_a_snapshot() {
+    let mut sim = Sim::new(3, Workload::LinKv);
+    let others = vec![String::from("n0"), String::from("n1")];
+    sim.partition(Partition {
+        from: Duration::ZERO,
+        until: Duration::from_secs(10),
+        groups: vec![others, vec![String::from("n2")]],
+    });
+    let history = run_lin_kv(&mut sim, Duration::from_secs(15), &mut Rng::new(0));
+    let verdict = linearizable::lin_kv(&history);
+    assert!(verdict.is_valid(), "{:#?}", verdict.errors);
+
+    let log = sim.log();
+    let installed = log
+        .iter()
+        .position(|delivery| {
+            delivery.message.dest == "n2"
+                && matches!(
+                    delivery.message.body.specific_fields,
+                    SpecificBodyFields::InstallSnapshot { .. }
+                )
+        })
+        .expect("n2 was never sent a snapshot");
+    // and it follows the leader again from there
+    assert!(log[installed..].iter().any(|delivery| {
+        delivery.message.src == "n2"
+            && matches!(
+                delivery.message.body.specific_fields,
+                SpecificBodyFields::AppendEntriesOk { success: true, .. }
+            )
+    }));
+}
+
+// The leader appends a write and gets it to both followers, but is cut off before
+// their acks reach it. Whoever takes over has the write without knowing it's
+// committed, and can only commit it through an entry of its own term.
+#[test]
+fn new_leader_commits_what_earlier_terms_left_uncommitted() {
+    let config = SimConfig {
+        latency: Latency::Constant(Duration::from_millis(10)),
+        ..SimConfig::default()
+    };
+    let mut sim = Sim::with_config(3, Workload::LinKv, config);
+    sim.run_for(Duration::from_secs(2));
+    let leader = sim
+        .log()
+        .iter()
+        .rev()
+        .find(|delivery| {
+            matches!(
+                delivery.message.body.specific_fields,
+                SpecificBodyFields::AppendEntries { .. }
+            )
+        })
+        .map(|delivery| delivery.message.src.clone())
+        .expect("no leader was elected");
+    let followers: Vec<String> = sim
+        .node_ids()
+        .into_iter()
+        .filter(|id| *id != leader)
+        .collect();
+
+    let now = sim.elapsed();
+    let write = SpecificBodyFields::Write {
+        key: json!(0),
+        value: json!(1),
+    };
+    let write_id = sim.client("c1", &leader, write);
+    // the append_entries goes out 10ms from now and its acks 10ms later
+    sim.partition(Partition {
+        from: now + Duration::from_millis(15),
+        until: Duration::from_secs(60),
+        groups: vec![vec![leader.clone()], followers.clone()],
+    });
+    sim.run_for(Duration::from_secs(3));
+    // committed on the other side without anything more from clients, and whoever
+    // leads there now answers it
+    assert!(
+        matches!(
+            sim.reply_to("c1", write_id)
+                .map(|reply| &reply.body.specific_fields),
+            Some(SpecificBodyFields::WriteOk)
+        ),
+        "the write was never committed"
+    );
+
+    for follower in &followers {
+        let read = SpecificBodyFields::Read {
+            key: Some(json!(0)),
+        };
+        let reply = sim.call("c2", follower, read, Duration::from_secs(2));
+        match reply.map(|reply| reply.body.specific_fields) {
+            Some(SpecificBodyFields::ReadOk { value, .. }) => assert_eq!(value, Some(json!(1))),
+            reply => panic!("read from {follower} got {reply:?}"),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    io::{self, Cursor, Write},
+    sync::{Arc, Mutex},
+};
+
+use flydis::{
+    node::Node,
+    protocol::{Message, SpecificBodyFields},
+    runtime,
+    workload::Workload,
+};
+
+// Output the test can still read once the runtime took its copy
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn threaded_runtime_answers_every_request_before_returning() {
+    let mut input = String::from(
+        r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":0}}"#,
+    );
+    input.push('\n');
+    for msg_id in 1..=100 {
+        input.push_str(&format!(
+            r#"{{"src":"c1","dest":"n0","body":{{"type":"echo","echo":"{msg_id}","msg_id":{msg_id}}}}}"#
+        ));
+        input.push('\n');
+    }
+    input.push_str(r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":101}}"#);
+    input.push('\n');
+
+    let output = Shared::default();
+    let node = Node::new(Workload::Broadcast);
+    runtime::run_threaded(node, Cursor::new(input), output.clone()).unwrap();
+
+    let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let replies: Vec<Message> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 102);
+    assert!(matches!(
+        replies[0].body.specific_fields,
+        SpecificBodyFields::InitOk
+    ));
+    // in the order they were asked
+    for (msg_id, reply) in (1..=100).zip(&replies[1..]) {
+        assert_eq!(reply.body.in_reply_to, Some(msg_id));
+        assert!(matches!(
+            &reply.body.specific_fields,
+            SpecificBodyFields::EchoOk { echo } if *echo == msg_id.to_string()
+        ));
+    }
+    // missing its echo
+    assert!(matches!(
+        replies[101].body.specific_fields,
+        SpecificBodyFields::Error { code: 12, .. }
+    ));
+}
@@ -0,0 +1,495 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use flydis::{
+    kv::KvService,
+    protocol::{SpecificBodyFields, TxnOperation},
+    sim::{self, Latency, Partition, Sim, SimConfig},
+    workload::Workload,
+};
+use serde_json::json;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Broadcast values to different nodes, give them `settle` to spread and check
+// every node ends up with all of them
+fn broadcast_converges(sim: &mut Sim, settle: Duration) {
+    let node_ids = sim.node_ids();
+    let seed = sim.seed();
+    for value in 0..20 {
+        let dest = &node_ids[value % node_ids.len()];
+        let body = SpecificBodyFields::Broadcast {
+            broadcast_message: value,
+        };
+        let reply = sim.call("c1", dest, body, CLIENT_TIMEOUT);
+        assert!(
+            matches!(
+                reply.map(|r| r.body.specific_fields),
+                Some(SpecificBodyFields::BroadcastOk)
+            ),
+            "broadcast of {value} to {dest} wasn't acknowledged (seed {seed})"
+        );
+    }
+
+    sim.run_for(settle);
+
+    let sent: HashSet<usize> = (0..20).collect();
+    for id in &node_ids {
+        let reply = sim.call(
+            "c1",
+            id,
+            SpecificBodyFields::Read { key: None },
+            CLIENT_TIMEOUT,
+        );
+        match reply.map(|r| r.body.specific_fields) {
+            Some(SpecificBodyFields::ReadOk {
+                messages: Some(messages),
+                ..
+            }) => assert_eq!(messages, sent, "{id} is missing values (seed {seed})"),
+            other => panic!("unexpected reply to read from {id}: {other:?} (seed {seed})"),
+        }
+    }
+}
+
+fn faulty_network(seed: u64) -> SimConfig {
+    SimConfig {
+        seed,
+        latency: Latency::Exponential {
+            mean: Duration::from_millis(50),
+        },
+        drop_rate: 0.2,
+        duplicate_rate: 0.1,
+        ..SimConfig::default()
+    }
+}
+
+#[test]
+fn broadcast_converges_on_a_line() {
+    let mut sim = Sim::new(5, Workload::Broadcast);
+    sim.topology(sim::line_topology(&sim.node_ids()));
+    broadcast_converges(&mut sim, Duration::from_secs(2));
+}
+
+#[test]
+fn broadcast_converges_when_fully_connected() {
+    let mut sim = Sim::new(5, Workload::Broadcast);
+    sim.topology(sim::full_topology(&sim.node_ids()));
+    broadcast_converges(&mut sim, Duration::from_secs(2));
+
+    // values are batched, so there should be far fewer gossip messages than values
+    let gossip = sim
+        .log()
+        .iter()
+        .filter(|delivery| {
+            matches!(
+                delivery.message.body.specific_fields,
+                SpecificBodyFields::MultiBroadcast { .. }
+            )
+        })
+        .count();
+    assert!(gossip < 20 * 4, "sent {gossip} multi_broadcast");
+}
+
+#[test]
+fn broadcast_converges_through_drops_and_partitions() {
+    for seed in 0..20 {
+        let mut sim = Sim::with_config(5, Workload::Broadcast, faulty_network(seed));
+        sim.topology(sim::line_topology(&sim.node_ids()));
+        sim.partition_periodically(Duration::from_secs(1), Duration::from_secs(6));
+        broadcast_converges(&mut sim, Duration::from_secs(10));
+        assert!(!sim.dropped().is_empty());
+    }
+}
+
+#[test]
+fn a_seed_replays_exactly() {
+    let run = |seed| {
+        let mut sim = Sim::with_config(5, Workload::Broadcast, faulty_network(seed));
+        sim.topology(sim::full_topology(&sim.node_ids()));
+        sim.partition_periodically(Duration::from_secs(1), Duration::from_secs(4));
+        broadcast_converges(&mut sim, Duration::from_secs(6));
+        // message bodies hold sets whose order changes between runs, so only
+        // compare who sent what to whom and when
+        sim.log()
+            .iter()
+            .map(|d| {
+                let body = &d.message.body;
+                (
+                    d.sent_at,
+                    d.at,
+                    d.message.src.clone(),
+                    d.message.dest.clone(),
+                    body.specific_fields.type_name(),
+                    body.msg_id,
+                    body.in_reply_to,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+}
+
+// Read the counter from `node` until it's `expected`, giving up after `deadline`
+fn counter_reaches(sim: &mut Sim, node: &str, expected: usize, deadline: Duration) -> bool {
+    let until = sim.elapsed() + deadline;
+    loop {
+        let reply = sim.call(
+            "c1",
+            node,
+            SpecificBodyFields::Read { key: None },
+            CLIENT_TIMEOUT,
+        );
+        if let Some(SpecificBodyFields::ReadOk {
+            value: Some(value), ..
+        }) = reply.map(|r| r.body.specific_fields)
+            && value == json!(expected)
+        {
+            return true;
+        }
+        if sim.elapsed() >= until {
+            return false;
+        }
+        sim.run_for(Duration::from_millis(500));
+    }
+}
+
+#[test]
+fn counter_converges_through_lost_kv_messages() {
+    for seed in 0..10 {
+        // no duplicates, maelstrom never delivers a write to seq-kv twice
+        let config = SimConfig {
+            seed,
+            latency: Latency::Exponential {
+                mean: Duration::from_millis(20),
+            },
+            drop_rate: 0.2,
+            ..SimConfig::default()
+        };
+        let mut sim = Sim::with_config(3, Workload::Counter, config);
+        let node_ids = sim.node_ids();
+        let mut totals = vec![0; node_ids.len()];
+        for i in 0..30 {
+            let delta = i % 5 + 1;
+            totals[i % node_ids.len()] += delta;
+            let body = SpecificBodyFields::Add { delta };
+            let reply = sim.call("c1", &node_ids[i % node_ids.len()], body, CLIENT_TIMEOUT);
+            assert!(matches!(
+                reply.map(|r| r.body.specific_fields),
+                Some(SpecificBodyFields::AddOk)
+            ));
+            sim.run_for(Duration::from_millis(50));
+        }
+        assert!(
+            sim.dropped()
+                .iter()
+                .any(|m| m.dest == "seq-kv" || m.src == "seq-kv"),
+            "nothing to or from seq-kv was lost (seed {seed})"
+        );
+
+        let sum = totals.iter().sum();
+        for id in &node_ids {
+            assert!(
+                counter_reaches(&mut sim, id, sum, Duration::from_secs(10)),
+                "{id} never read {sum} (seed {seed})"
+            );
+        }
+        // every node only ever writes its own key
+        let kv = sim.kv(KvService::Seq).unwrap();
+        for (id, total) in node_ids.iter().zip(totals) {
+            assert_eq!(kv.get(format!("counter-{id}")), Some(&json!(total)));
+        }
+    }
+}
+
+#[test]
+fn counter_writes_again_once_seq_kv_is_back() {
+    let mut sim = Sim::new(3, Workload::Counter);
+    let from = sim.elapsed();
+    sim.partition(Partition {
+        from,
+        until: from + Duration::from_secs(3),
+        groups: vec![vec![String::from("n0")], vec![String::from("seq-kv")]],
+    });
+    let reply = sim.call(
+        "c1",
+        "n0",
+        SpecificBodyFields::Add { delta: 5 },
+        CLIENT_TIMEOUT,
+    );
+    assert!(matches!(
+        reply.map(|r| r.body.specific_fields),
+        Some(SpecificBodyFields::AddOk)
+    ));
+
+    sim.run_for(Duration::from_secs(2));
+    // the first write timed out and was tried again, in vain
+    let lost_writes = sim
+        .dropped()
+        .iter()
+        .filter(|m| {
+            m.src == "n0" && matches!(m.body.specific_fields, SpecificBodyFields::Write { .. })
+        })
+        .count();
+    assert_eq!(lost_writes, 2);
+    assert!(counter_reaches(&mut sim, "n1", 0, Duration::ZERO));
+    assert!(
+        sim.kv(KvService::Seq)
+            .and_then(|kv| kv.get("counter-n0"))
+            .is_none()
+    );
+
+    assert!(counter_reaches(&mut sim, "n1", 5, Duration::from_secs(3)));
+    assert_eq!(
+        sim.kv(KvService::Seq).unwrap().get("counter-n0"),
+        Some(&json!(5))
+    );
+}
+
+fn write(key: usize, value: usize) -> TxnOperation {
+    (String::from("w"), key, Some(value))
+}
+
+// What `node` reads for every key in `keys`, in a single transaction
+fn read_registers(sim: &mut Sim, node: &str, keys: &[usize]) -> Vec<Option<usize>> {
+    let txn = keys
+        .iter()
+        .map(|key| (String::from("r"), *key, None))
+        .collect();
+    let reply = sim.call("c1", node, SpecificBodyFields::Txn { txn }, CLIENT_TIMEOUT);
+    match reply.map(|r| r.body.specific_fields) {
+        Some(SpecificBodyFields::TxnOk { txn }) => txn.into_iter().map(|(_, _, v)| v).collect(),
+        other => panic!("unexpected reply to a read from {node}: {other:?}"),
+    }
+}
+
+#[test]
+fn conflicting_txns_converge_through_drops_and_partitions() {
+    let keys = [0, 1, 2];
+    for seed in 0..10 {
+        let mut sim = Sim::with_config(3, Workload::Txn, faulty_network(seed));
+        let node_ids = sim.node_ids();
+        sim.partition_periodically(Duration::from_secs(1), Duration::from_secs(4));
+        // two nodes write the same key at the same time, every round
+        for round in 0..40 {
+            let key = keys[round % keys.len()];
+            for (i, node) in [round % 3, (round + 1) % 3].into_iter().enumerate() {
+                let txn = vec![write(key, round * 10 + i)];
+                sim.client("c1", &node_ids[node], SpecificBodyFields::Txn { txn });
+            }
+            sim.run_for(Duration::from_millis(100));
+        }
+        assert!(
+            sim.dropped().iter().any(|m| matches!(
+                m.body.specific_fields,
+                SpecificBodyFields::ReplicateTxn { .. }
+            )),
+            "no replication was lost (seed {seed})"
+        );
+
+        // long enough for the replications lost to the partitions to be retried
+        sim.run_for(Duration::from_secs(5));
+        let expected = read_registers(&mut sim, &node_ids[0], &keys);
+        assert!(expected.iter().all(Option::is_some), "seed {seed}");
+        for id in &node_ids[1..] {
+            assert_eq!(
+                read_registers(&mut sim, id, &keys),
+                expected,
+                "{id} disagrees with n0 (seed {seed})"
+            );
+        }
+    }
+}
+
+#[test]
+fn txns_settle_on_the_write_with_the_highest_clock() {
+    let config = SimConfig {
+        latency: Latency::Constant(Duration::from_millis(10)),
+        ..SimConfig::default()
+    };
+    let mut sim = Sim::with_config(2, Workload::Txn, config);
+
+    // same clock, the node id decides
+    sim.client(
+        "c1",
+        "n0",
+        SpecificBodyFields::Txn {
+            txn: vec![write(0, 1)],
+        },
+    );
+    sim.client(
+        "c1",
+        "n1",
+        SpecificBodyFields::Txn {
+            txn: vec![write(0, 2)],
+        },
+    );
+    sim.run_for(Duration::from_millis(100));
+    for id in ["n0", "n1"] {
+        assert_eq!(read_registers(&mut sim, id, &[0]), [Some(2)]);
+    }
+
+    // cut off, n0 writes more than n1 does, so its clock is ahead even though
+    // n1 writes last
+    let from = sim.elapsed();
+    sim.partition(Partition {
+        from,
+        until: from + Duration::from_secs(2),
+        groups: vec![vec![String::from("n0")], vec![String::from("n1")]],
+    });
+    for value in 10..13 {
+        let txn = vec![write(0, value)];
+        sim.call("c1", "n0", SpecificBodyFields::Txn { txn }, CLIENT_TIMEOUT);
+    }
+    let txn = vec![write(0, 20)];
+    sim.call("c1", "n1", SpecificBodyFields::Txn { txn }, CLIENT_TIMEOUT);
+    assert_eq!(read_registers(&mut sim, "n0", &[0]), [Some(12)]);
+    assert_eq!(read_registers(&mut sim, "n1", &[0]), [Some(20)]);
+
+    // the lost replications are retried once the partition heals
+    sim.run_for(Duration::from_secs(3));
+    for id in ["n0", "n1"] {
+        assert_eq!(read_registers(&mut sim, id, &[0]), [Some(12)]);
+    }
+}
+
+// Longer than nodes wait on each other, so that a forwarded send that got lost
+// still gets its error back
+const FORWARDED_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn kafka_send(sim: &mut Sim, node: &str, key: &str, msg: usize) -> SpecificBodyFields {
+    let body = SpecificBodyFields::Send {
+        key: key.to_string(),
+        msg,
+    };
+    match sim.call("c1", node, body, FORWARDED_TIMEOUT) {
+        Some(reply) => reply.body.specific_fields,
+        None => panic!("{node} never answered the send of {msg}"),
+    }
+}
+
+// The node a send of `key` to n0 was forwarded to, or n0 if it wasn't
+fn kafka_owner(sim: &mut Sim, key: &str) -> String {
+    let sent = sim.log().len();
+    kafka_send(sim, "n0", key, 0);
+    sim.log()[sent..]
+        .iter()
+        .map(|delivery| &delivery.message)
+        .find(|m| {
+            m.src == "n0" && matches!(m.body.specific_fields, SpecificBodyFields::Send { .. })
+        })
+        .map_or(String::from("n0"), |m| m.dest.clone())
+}
+
+fn committed(sim: &mut Sim, node: &str, key: &str) -> Option<usize> {
+    let keys = vec![key.to_string()];
+    let body = SpecificBodyFields::ListCommittedOffsets { keys };
+    match sim
+        .call("c1", node, body, CLIENT_TIMEOUT)
+        .map(|r| r.body.specific_fields)
+    {
+        Some(SpecificBodyFields::ListCommittedOffsetsOk { offsets }) => offsets.get(key).copied(),
+        other => panic!("unexpected reply to list_committed_offsets from {node}: {other:?}"),
+    }
+}
+
+fn poll(sim: &mut Sim, node: &str, key: &str) -> Vec<(usize, usize)> {
+    let offsets = HashMap::from([(key.to_string(), 0)]);
+    match sim
+        .call(
+            "c1",
+            node,
+            SpecificBodyFields::Poll { offsets },
+            CLIENT_TIMEOUT,
+        )
+        .map(|r| r.body.specific_fields)
+    {
+        Some(SpecificBodyFields::PollOk { mut msgs }) => msgs.remove(key).unwrap_or_default(),
+        other => panic!("unexpected reply to poll from {node}: {other:?}"),
+    }
+}
+
+fn kafka_sim() -> Sim {
+    let config = SimConfig {
+        latency: Latency::Constant(Duration::from_millis(10)),
+        ..SimConfig::default()
+    };
+    Sim::with_config(3, Workload::Kafka, config)
+}
+
+#[test]
+fn kafka_commits_on_any_node_reach_the_others() {
+    let mut sim = kafka_sim();
+    let owner = kafka_owner(&mut sim, "k");
+    let others: Vec<String> = sim.node_ids().into_iter().filter(|n| *n != owner).collect();
+    let (committer, reader) = (&others[0], &others[1]);
+    for msg in 1..3 {
+        assert!(matches!(
+            kafka_send(&mut sim, committer, "k", msg),
+            SpecificBodyFields::SendOk { offset } if offset == msg
+        ));
+    }
+
+    // the reader doesn't hear about the commit until the partition heals
+    let from = sim.elapsed();
+    sim.partition(Partition {
+        from,
+        until: from + Duration::from_secs(2),
+        groups: vec![vec![committer.clone()], vec![reader.clone()]],
+    });
+    let offsets = HashMap::from([(String::from("k"), 1)]);
+    let reply = sim.call(
+        "c1",
+        committer,
+        SpecificBodyFields::CommitOffsets { offsets },
+        CLIENT_TIMEOUT,
+    );
+    assert!(matches!(
+        reply.map(|r| r.body.specific_fields),
+        Some(SpecificBodyFields::CommitOffsetsOk)
+    ));
+    assert_eq!(committed(&mut sim, committer, "k"), Some(1));
+    assert_eq!(committed(&mut sim, &owner, "k"), Some(1));
+    assert_eq!(committed(&mut sim, reader, "k"), None);
+
+    sim.run_for(Duration::from_secs(3));
+    assert_eq!(committed(&mut sim, reader, "k"), Some(1));
+    assert_eq!(poll(&mut sim, reader, "k"), [(0, 0), (1, 1), (2, 2)]);
+}
+
+#[test]
+fn kafka_sends_fail_while_the_owner_is_cut_off() {
+    let mut sim = kafka_sim();
+    let owner = kafka_owner(&mut sim, "k");
+    let others: Vec<String> = sim.node_ids().into_iter().filter(|n| *n != owner).collect();
+    let from = sim.elapsed();
+    sim.partition(Partition {
+        from,
+        until: from + Duration::from_secs(3),
+        groups: vec![vec![owner.clone()], others.clone()],
+    });
+
+    // nobody but the owner hands out offsets, so the others can only give up
+    assert!(matches!(
+        kafka_send(&mut sim, &others[0], "k", 1),
+        SpecificBodyFields::Error { code: 0, .. }
+    ));
+    // while the owner goes on serving what reaches it
+    assert!(matches!(
+        kafka_send(&mut sim, &owner, "k", 2),
+        SpecificBodyFields::SendOk { offset: 1 }
+    ));
+    assert_eq!(poll(&mut sim, &others[1], "k"), [(0, 0)]);
+
+    sim.run_for(Duration::from_secs(3));
+    assert!(matches!(
+        kafka_send(&mut sim, &others[0], "k", 3),
+        SpecificBodyFields::SendOk { offset: 2 }
+    ));
+    sim.run_for(Duration::from_millis(100));
+    for id in sim.node_ids() {
+        assert_eq!(poll(&mut sim, &id, "k"), [(0, 0), (1, 2), (2, 3)], "{id}");
+    }
+}
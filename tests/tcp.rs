@@ -0,0 +1,251 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use flydis::{
+    node::Node,
+    protocol::{Body, Message, SpecificBodyFields},
+    tcp::{self, ClusterConfig},
+    workload::Workload,
+};
+
+// A client connected to one of the nodes
+struct Client {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    dest: String,
+    msg_id: usize,
+}
+
+impl Client {
+    fn connect(config: &ClusterConfig, dest: &str) -> Self {
+        let writer = TcpStream::connect(config.nodes[dest]).unwrap();
+        writer
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(writer.try_clone().unwrap()),
+            writer,
+            dest: dest.to_string(),
+            msg_id: 0,
+        }
+    }
+
+    fn request(&mut self, body: SpecificBodyFields) -> SpecificBodyFields {
+        self.msg_id += 1;
+        let message = Message {
+            src: String::from("c1"),
+            dest: self.dest.clone(),
+            body: Body {
+                specific_fields: body,
+                msg_id: Some(self.msg_id),
+                in_reply_to: None,
+            },
+        };
+        writeln!(self.writer, "{}", serde_json::to_string(&message).unwrap()).unwrap();
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let reply: Message = serde_json::from_str(&line).unwrap();
+        assert_eq!(reply.body.in_reply_to, Some(self.msg_id));
+        reply.body.specific_fields
+    }
+}
+
+fn start_cluster(count: usize, workload: Workload) -> ClusterConfig {
+    start_cluster_with(count, workload, BTreeMap::new())
+}
+
+// Same with the nodes in `down` in the config too, though they're never started
+fn start_cluster_with(
+    count: usize,
+    workload: Workload,
+    down: BTreeMap<String, SocketAddr>,
+) -> ClusterConfig {
+    let listeners: Vec<(String, TcpListener)> = (0..count)
+        .map(|i| (format!("n{i}"), TcpListener::bind("127.0.0.1:0").unwrap()))
+        .collect();
+    let mut nodes: BTreeMap<_, _> = listeners
+        .iter()
+        .map(|(id, listener)| (id.clone(), listener.local_addr().unwrap()))
+        .collect();
+    nodes.extend(down);
+    let config = ClusterConfig { nodes };
+    for (id, listener) in listeners {
+        let config = config.clone();
+        // the node isn't Send, so it's made on the thread it runs on
+        thread::spawn(move || tcp::serve_on(listener, Node::new(workload), &id, &config));
+    }
+    config
+}
+
+#[test]
+fn broadcast_values_reach_every_node_over_tcp() {
+    let config = start_cluster(3, Workload::Broadcast);
+    let mut clients: Vec<Client> = config
+        .nodes
+        .keys()
+        .map(|id| Client::connect(&config, id))
+        .collect();
+    // a line
+    let topology: HashMap<String, Vec<String>> = HashMap::from([
+        (String::from("n0"), vec![String::from("n1")]),
+        (
+            String::from("n1"),
+            vec![String::from("n0"), String::from("n2")],
+        ),
+        (String::from("n2"), vec![String::from("n1")]),
+    ]);
+    for client in &mut clients {
+        let body = SpecificBodyFields::Topology {
+            topology: topology.clone(),
+        };
+        assert!(matches!(
+            client.request(body),
+            SpecificBodyFields::TopologyOk
+        ));
+    }
+    let broadcast = SpecificBodyFields::Broadcast {
+        broadcast_message: 7,
+    };
+    assert!(matches!(
+        clients[0].request(broadcast),
+        SpecificBodyFields::BroadcastOk
+    ));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let read = clients[2].request(SpecificBodyFields::Read { key: None });
+        let SpecificBodyFields::ReadOk {
+            messages: Some(messages),
+            ..
+        } = read
+        else {
+            panic!("expected a read_ok, got {read:?}");
+        };
+        if messages.contains(&7) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "7 never made it to n2");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn kafka_sends_are_forwarded_to_the_owner_over_tcp() {
+    let config = start_cluster(3, Workload::Kafka);
+    let mut clients: Vec<Client> = config
+        .nodes
+        .keys()
+        .map(|id| Client::connect(&config, id))
+        .collect();
+    // every key goes through n0, whichever node owns it
+    let mut sent = HashMap::new();
+    for key in ["a", "b", "c", "d", "e", "f"] {
+        let send = SpecificBodyFields::Send {
+            key: key.to_string(),
+            msg: 1,
+        };
+        match clients[0].request(send) {
+            SpecificBodyFields::SendOk { offset } => sent.insert(key.to_string(), offset),
+            reply => panic!("expected a send_ok, got {reply:?}"),
+        };
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let offsets = sent.clone();
+        let read = clients[2].request(SpecificBodyFields::Poll { offsets });
+        let SpecificBodyFields::PollOk { msgs } = read else {
+            panic!("expected a poll_ok, got {read:?}");
+        };
+        if sent
+            .iter()
+            .all(|(key, offset)| msgs.get(key).is_some_and(|msgs| msgs == &[(*offset, 1)]))
+        {
+            break;
+        }
+        assert!(Instant::now() < deadline, "n2 never got every message");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// A listener that never accepts, with its backlog full so that connecting to it
+// hangs until the connection times out
+fn unresponsive() -> (TcpListener, Vec<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut backlog = Vec::new();
+    while let Ok(stream) = TcpStream::connect_timeout(&addr, Duration::from_millis(50)) {
+        backlog.push(stream);
+    }
+    (listener, backlog)
+}
+
+#[test]
+fn a_peer_that_never_answers_holds_up_nothing_else() {
+    let (n2, _backlog) = unresponsive();
+    let down = BTreeMap::from([(String::from("n2"), n2.local_addr().unwrap())]);
+    let config = start_cluster_with(2, Workload::Broadcast, down);
+    let mut n0 = Client::connect(&config, "n0");
+    let mut n1 = Client::connect(&config, "n1");
+    let topology = HashMap::from([
+        (
+            String::from("n0"),
+            vec![String::from("n1"), String::from("n2")],
+        ),
+        (String::from("n1"), vec![String::from("n0")]),
+    ]);
+    for client in [&mut n0, &mut n1] {
+        let body = SpecificBodyFields::Topology {
+            topology: topology.clone(),
+        };
+        client.request(body);
+    }
+    let broadcast = SpecificBodyFields::Broadcast {
+        broadcast_message: 7,
+    };
+    n0.request(broadcast);
+
+    // n0 keeps trying to gossip to n2 meanwhile, which never got in the way of
+    // answering us
+    for round in 0..30 {
+        let asked = Instant::now();
+        let echo = SpecificBodyFields::Echo {
+            echo: round.to_string(),
+        };
+        assert!(matches!(
+            n0.request(echo),
+            SpecificBodyFields::EchoOk { .. }
+        ));
+        assert!(
+            asked.elapsed() < Duration::from_millis(150),
+            "echo {round} took {:?}",
+            asked.elapsed()
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    let read = n1.request(SpecificBodyFields::Read { key: None });
+    assert!(
+        matches!(&read, SpecificBodyFields::ReadOk { messages: Some(messages), .. } if messages.contains(&7)),
+        "7 never made it to n1: {read:?}"
+    );
+}
+
+#[test]
+fn workloads_relying_on_a_kv_service_are_refused() {
+    let error = tcp::check_workload(Workload::Counter).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    assert!(error.to_string().contains("seq-kv"));
+    for workload in [
+        Workload::Broadcast,
+        Workload::Kafka,
+        Workload::Txn,
+        Workload::LinKv,
+    ] {
+        assert!(tcp::check_workload(workload).is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common::{init, message, request};
+use flydis::{
+    node::{Node, Output, TimerId},
+    protocol::SpecificBodyFields,
+    wal::Fsync,
+    workload::Workload,
+};
+
+// A fresh file for every test, in case a previous run left one
+fn wal_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("flydis-{}-{name}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn start(workload: Workload, path: &PathBuf, fsync: Fsync) -> (Node, Vec<Output>) {
+    let mut node = Node::new(workload).with_wal(path, fsync).unwrap();
+    let outputs = node.handle(message("c0", 0, init()), Instant::now());
+    (node, outputs)
+}
+
+fn sent(outputs: Vec<Output>) -> Vec<SpecificBodyFields> {
+    outputs
+        .into_iter()
+        .filter_map(|output| match output {
+            Output::Send(message) => Some(message.body.specific_fields),
+            Output::Timer { .. } => None,
+        })
+        .collect()
+}
+
+fn timers(outputs: &[Output]) -> Vec<TimerId> {
+    outputs
+        .iter()
+        .filter_map(|output| match output {
+            Output::Timer { id, .. } => Some(*id),
+            Output::Send(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn broadcast_values_survive_a_restart() {
+    let path = wal_path("broadcast");
+    let (mut node, _) = start(Workload::Broadcast, &path, Fsync::Always);
+    let topology = HashMap::from([(String::from("n0"), vec![String::from("n1")])]);
+    request(&mut node, 1, SpecificBodyFields::Topology { topology });
+    for value in [1, 2] {
+        let broadcast = SpecificBodyFields::Broadcast {
+            broadcast_message: value,
+        };
+        request(&mut node, value + 1, broadcast);
+    }
+    let messages = [2, 3].into();
+    sent(node.handle(
+        message("n1", 0, SpecificBodyFields::MultiBroadcast { messages }),
+        Instant::now(),
+    ));
+    drop(node);
+
+    let (mut node, outputs) = start(Workload::Broadcast, &path, Fsync::Always);
+    match &request(&mut node, 4, SpecificBodyFields::Read { key: None })[..] {
+        [
+            SpecificBodyFields::ReadOk {
+                messages: Some(messages),
+                ..
+            },
+        ] => assert_eq!(*messages, [1, 2, 3].into()),
+        replies => panic!("expected a read_ok, got {replies:?}"),
+    }
+    // and nothing tells us n1 got them, so they're gossiped again
+    let gossip = timers(&outputs)
+        .into_iter()
+        .flat_map(|id| sent(node.fire(id, Instant::now() + Duration::from_secs(1))))
+        .collect::<Vec<_>>();
+    assert!(matches!(
+        &gossip[..],
+        [SpecificBodyFields::MultiBroadcast { messages }] if *messages == [1, 2, 3].into()
+    ));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn kafka_logs_and_offsets_survive_a_torn_write() {
+    let path = wal_path("kafka");
+    let (mut node, _) = start(Workload::Kafka, &path, Fsync::Every(Duration::ZERO));
+    for msg in [10, 11, 12] {
+        let send = SpecificBodyFields::Send {
+            key: String::from("k"),
+            msg,
+        };
+        request(&mut node, msg, send);
+    }
+    let offsets = HashMap::from([(String::from("k"), 1)]);
+    request(&mut node, 13, SpecificBodyFields::CommitOffsets { offsets });
+    drop(node);
+
+    // the process died halfway through appending
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(br#"{"type":"kafka_entry","key":"k","off"#)
+        .unwrap();
+    drop(file);
+
+    for restart in 0..2 {
+        let (mut node, _) = start(Workload::Kafka, &path, Fsync::Never);
+        let offsets = HashMap::from([(String::from("k"), 0)]);
+        let expected = if restart == 0 {
+            vec![(0, 10), (1, 11), (2, 12)]
+        } else {
+            vec![(0, 10), (1, 11), (2, 12), (3, 13)]
+        };
+        match &request(&mut node, 1, SpecificBodyFields::Poll { offsets })[..] {
+            [SpecificBodyFields::PollOk { msgs }] => assert_eq!(msgs["k"], expected),
+            replies => panic!("expected a poll_ok, got {replies:?}"),
+        }
+        let keys = vec![String::from("k")];
+        match &request(
+            &mut node,
+            2,
+            SpecificBodyFields::ListCommittedOffsets { keys },
+        )[..]
+        {
+            [SpecificBodyFields::ListCommittedOffsetsOk { offsets }] => {
+                assert_eq!(offsets["k"], 1)
+            }
+            replies => panic!("expected committed offsets, got {replies:?}"),
+        }
+        // appended after the torn record was dropped, so it's there next time
+        let send = SpecificBodyFields::Send {
+            key: String::from("k"),
+            msg: 13,
+        };
+        if restart == 0 {
+            request(&mut node, 3, send);
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn a_corrupt_record_before_the_last_one_refuses_to_open() {
+    let path = wal_path("corrupt");
+    let (mut node, _) = start(Workload::Kafka, &path, Fsync::Always);
+    for msg in [10, 11] {
+        let send = SpecificBodyFields::Send {
+            key: String::from("k"),
+            msg,
+        };
+        request(&mut node, msg, send);
+    }
+    drop(node);
+
+    // the first record loses its end, with the second still after it
+    let wal = std::fs::read(&path).unwrap();
+    let first = wal.iter().position(|byte| *byte == b'\n').unwrap();
+    let mut corrupt = wal[..first / 2].to_vec();
+    corrupt.extend_from_slice(&wal[first..]);
+    std::fs::write(&path, &corrupt).unwrap();
+
+    match Node::new(Workload::Kafka).with_wal(&path, Fsync::Always) {
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+        Ok(_) => panic!("opened a corrupt wal"),
+    }
+    // and nothing after it was thrown away
+    assert_eq!(std::fs::read(&path).unwrap(), corrupt);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn raft_log_survives_a_restart() {
+    let path = wal_path("raft");
+    for (restart, expected) in [(0, None), (1, Some(2)), (2, Some(3))] {
+        let (mut node, outputs) = start(Workload::LinKv, &path, Fsync::Always);
+        // alone, the node elects itself once its election deadline passes
+        let tick = timers(&outputs)[0];
+        node.fire(tick, Instant::now() + Duration::from_secs(2));
+
+        let read = SpecificBodyFields::Read {
+            key: Some(0.into()),
+        };
+        match (&request(&mut node, restart * 2, read)[..], expected) {
+            ([SpecificBodyFields::ReadOk { value, .. }], Some(expected)) => {
+                assert_eq!(*value, Some(expected.into()))
+            }
+            ([SpecificBodyFields::Error { code: 20, .. }], None) => {}
+            (replies, _) => panic!("unexpected {replies:?} after {restart} restarts"),
+        }
+        let write = SpecificBodyFields::Write {
+            key: 0.into(),
+            value: (restart + 2).into(),
+        };
+        assert!(matches!(
+            request(&mut node, restart * 2 + 1, write)[..],
+            [SpecificBodyFields::WriteOk]
+        ));
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn raft_snapshots_survive_a_restart() {
+    let path = wal_path("raft-snapshot");
+    for restart in 0..2 {
+        let (mut node, outputs) = start(Workload::LinKv, &path, Fsync::Never);
+        let tick = timers(&outputs)[0];
+        node.fire(tick, Instant::now() + Duration::from_secs(2));
+        if restart > 0 {
+            let read = SpecificBodyFields::Read {
+                key: Some(0.into()),
+            };
+            assert!(matches!(
+                &request(&mut node, 0, read)[..],
+                [SpecificBodyFields::ReadOk { value: Some(value), .. }] if *value == 1500
+            ));
+        }
+        // enough to compact the log
+        for value in 1..=1500 {
+            let write = SpecificBodyFields::Write {
+                key: 0.into(),
+                value: value.into(),
+            };
+            request(&mut node, value, write);
+        }
+    }
+    let wal = std::fs::read_to_string(&path).unwrap();
+    assert!(wal.contains(r#""type":"raft_snapshot""#));
+    let _ = std::fs::remove_file(&path);
+}